@@ -0,0 +1,56 @@
+// Generates the word lists of `keyarmor` from `src/keyarmor/wordlists/*.txt` (one word per line),
+// normalized the same way typed words are, so they can be looked up directly.
+
+use std::{
+    env, fs,
+    io::{BufWriter, Write as _},
+    path::Path,
+};
+
+use unicode_normalization::UnicodeNormalization as _;
+
+const WORDLISTS: &str = "src/keyarmor/wordlists";
+
+fn main() {
+    println!("cargo:rerun-if-changed={WORDLISTS}");
+    let out = Path::new(&env::var_os("OUT_DIR").expect("OUT_DIR is not set")).join("wordlists.rs");
+    let mut out = BufWriter::new(fs::File::create(out).expect("failed to create wordlists.rs"));
+
+    let mut paths: Vec<_> = fs::read_dir(WORDLISTS)
+        .expect("failed to read word lists")
+        .map(|entry| entry.expect("failed to read word lists").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "txt"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let name = path
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .expect("word list name is not valid UTF-8")
+            .to_uppercase();
+        let contents = fs::read_to_string(&path).expect("failed to read word list");
+        let words: Vec<String> = contents
+            .lines()
+            .map(|word| word.trim().nfkd().collect::<String>().to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+        // 11 bits per word for BIP39 lists, 8 for the halves of the PGP list
+        assert!(
+            words.len() == 2048 || words.len() == 256,
+            "{} has {} words instead of 2048 or 256",
+            path.display(),
+            words.len()
+        );
+
+        let mut set = phf_codegen::OrderedSet::new();
+        for word in &words {
+            set.entry(word.as_str());
+        }
+        writeln!(
+            out,
+            "pub static {name}: phf::OrderedSet<&str> = {};",
+            set.build()
+        )
+        .expect("failed to write wordlists.rs");
+    }
+}
@@ -26,14 +26,35 @@ in
       description = "Directory where tmpfs with decrypted secrets will be created";
     };
     keys = mkOption {
-      type = types.attrsOf types.path;
+      type = types.attrsOf (types.either types.path types.str);
       description = ''
         Where to find encryption keys (generated with `classified gen-key`).
+        age identities and unencrypted OpenSSH ed25519 keys (e.g. the host key) work too.
 
-        These files should belong to root:root and have permissions 400 or 600.
+        Either a path, which should belong to root:root and have permissions 400 or 600,
+        or `credential:NAME` for a systemd credential of the service (see `LoadCredential=`),
+        `env:NAME` for an environment variable, `fd:N` for an inherited file descriptor
+        or `command:PROGRAM ARGS...` for the output of a command (arguments are separated by
+        whitespace; it has a minute to print the key and has to exit successfully).
       '';
       default = { };
     };
+    defaultKey = mkOption {
+      type = types.nullOr (types.either types.str (types.listOf types.str));
+      default = null;
+      description = ''
+        Key (or ordered list of keys) for files that don't specify `key`, see `files.<name>.key`.
+      '';
+    };
+    trustedSigners = mkOption {
+      type = types.listOf types.str;
+      default = [ ];
+      description = ''
+        Public keys (`ed25519:...`, as printed by `classified public-key --signing`) of whoever
+        may encrypt secrets for this host. If not empty, files that aren't signed by one of them
+        with `classified encrypt --sign` are refused.
+      '';
+    };
     multipleServices = mkOption {
       type = types.bool;
       default = false;
@@ -43,12 +64,16 @@ in
       type = types.attrsOf (types.submodule {
         options = {
           key = mkOption {
-            type = types.nullOr types.str;
+            type = types.nullOr (types.either types.str (types.listOf types.str));
             default = null;
             description = ''
               Name of the encryption key to use (as specified in `keys`).
 
-              It's allowed to be null if there's exactly one key configured.
+              Can also be a list of names to try in order, e.g. `[ "new" "old" ]` while rotating keys:
+              a warning is logged when the file can only be decrypted with one of the later, deprecated keys.
+
+              If null, `defaultKey` is used if set, otherwise the key is picked by the key ID stored
+              in the encrypted file (or the first configured key for files without one).
             '';
           };
           encrypted = mkOption {
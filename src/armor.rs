@@ -0,0 +1,280 @@
+// Text forms of binary files: PEM-like armor (`-----BEGIN <LABEL>-----`, base64 in 64-column
+// lines, `-----END <LABEL>-----`) and, for older files, bare base64. Whitespace anywhere in the
+// base64 is ignored, so CRLF line endings, rewrapped lines and pasted blobs are all fine.
+
+use std::io::{self, Read, Write};
+
+const READ_SIZE: usize = 4096;
+pub const COLUMNS: usize = 64;
+// longer lines starting with a dash are not armor boundaries
+const MAX_BOUNDARY_LEN: usize = 80;
+
+fn boundary(kind: &str, label: &str) -> String {
+    format!("-----{kind} {label}-----")
+}
+
+// Armors everything written to it
+pub struct Writer<W: Write> {
+    encoder: base64::write::EncoderWriter<LineWriter<W>>,
+    label: &'static str,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(mut inner: W, label: &'static str) -> io::Result<Self> {
+        writeln!(inner, "{}", boundary("BEGIN", label))?;
+        Ok(Self {
+            encoder: base64::write::EncoderWriter::new(
+                LineWriter::new(inner, COLUMNS),
+                base64::STANDARD,
+            ),
+            label,
+        })
+    }
+
+    // Writes the last line and the end boundary
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut inner = self.encoder.finish()?.finish()?;
+        writeln!(inner, "{}", boundary("END", self.label))?;
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+// Breaks output into lines of `width` characters
+pub struct LineWriter<W> {
+    inner: W,
+    width: usize,
+    column: usize,
+}
+
+impl<W: Write> LineWriter<W> {
+    pub fn new(inner: W, width: usize) -> Self {
+        Self {
+            inner,
+            width,
+            column: 0,
+        }
+    }
+
+    // Terminates the last line
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.column > 0 {
+            self.inner.write_all(b"\n")?;
+        }
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for LineWriter<W> {
+    // Always consumes the whole buffer, `EncoderWriter` doesn't like short writes
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            if self.column == self.width {
+                self.inner.write_all(b"\n")?;
+                self.column = 0;
+            }
+            let n = rest.len().min(self.width - self.column);
+            self.inner.write_all(&rest[..n])?;
+            self.column += n;
+            rest = &rest[n..];
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum State {
+    // nothing but whitespace so far
+    Start,
+    Base64 {
+        armored: bool,
+    },
+    // inside a line starting with a dash, `first` if nothing came before it
+    Boundary {
+        first: bool,
+        armored: bool,
+        line: Vec<u8>,
+    },
+    // after the end boundary
+    Done,
+}
+
+// Streaming decoder of armored or bare base64 input
+pub struct Reader<R> {
+    inner: R,
+    begin: String,
+    end: String,
+    state: State,
+    // characters that don't form a full base64 group yet
+    pending: Vec<u8>,
+    decoded: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R, label: &str) -> Self {
+        Self {
+            inner,
+            begin: boundary("BEGIN", label),
+            end: boundary("END", label),
+            state: State::Start,
+            pending: Vec::new(),
+            decoded: Vec::new(),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    fn push(&mut self, c: u8) -> io::Result<()> {
+        match &mut self.state {
+            State::Boundary {
+                first,
+                armored,
+                line,
+            } => {
+                if c != b'\n' {
+                    if line.len() == MAX_BOUNDARY_LEN {
+                        return Err(invalid("malformed armor"));
+                    }
+                    line.push(c);
+                    return Ok(());
+                }
+                let line = line.trim_ascii();
+                self.state = if *first && line == self.begin.as_bytes() {
+                    State::Base64 { armored: true }
+                } else if *armored && line == self.end.as_bytes() {
+                    State::Done
+                } else {
+                    return Err(invalid("malformed armor"));
+                };
+            }
+            _ if c.is_ascii_whitespace() => {}
+            State::Done => return Err(invalid("unexpected data after armor")),
+            State::Start if c == b'-' => {
+                self.state = State::Boundary {
+                    first: true,
+                    armored: false,
+                    line: vec![c],
+                };
+            }
+            &mut State::Base64 { armored } if c == b'-' => {
+                self.state = State::Boundary {
+                    first: false,
+                    armored,
+                    line: vec![c],
+                };
+            }
+            State::Start => {
+                self.state = State::Base64 { armored: false };
+                self.pending.push(c);
+            }
+            State::Base64 { .. } => self.pending.push(c),
+        }
+        Ok(())
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let mut raw = [0_u8; READ_SIZE];
+        let n = self.inner.read(&mut raw)?;
+        for &c in &raw[..n] {
+            self.push(c)?;
+        }
+
+        let usable = if n == 0 {
+            self.eof = true;
+            // the end boundary may lack a newline
+            if let State::Boundary { .. } = self.state {
+                self.push(b'\n')?;
+            }
+            if self.state == (State::Base64 { armored: true }) {
+                return Err(invalid("armor is truncated"));
+            }
+            self.pending.len()
+        } else {
+            self.pending.len() / 4 * 4
+        };
+
+        self.decoded.clear();
+        self.pos = 0;
+        base64::decode_config_buf(&self.pending[..usable], base64::STANDARD, &mut self.decoded)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        self.pending.drain(..usable);
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.decoded.len() {
+            if self.eof {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+
+        let n = buf.len().min(self.decoded.len() - self.pos);
+        buf[..n].copy_from_slice(&self.decoded[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::io::{Read as _, Write as _};
+
+    use proptest::{collection::vec, prop_assert, prop_assert_eq, proptest};
+
+    use super::{Reader, Writer};
+
+    fn decode(armored: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        Reader::new(armored, "TEST").read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    proptest! {
+        #[test]
+        fn unarmor(input in vec(0..=u8::MAX, 0..16384), wrap in 1..100_usize) {
+            let mut writer = Writer::new(Vec::new(), "TEST").unwrap();
+            writer.write_all(&input).unwrap();
+            let armored = writer.finish().unwrap();
+            prop_assert_eq!(decode(&armored).unwrap(), input.clone());
+
+            // CRLF and a missing final newline
+            let crlf = String::from_utf8(armored.clone()).unwrap().replace('\n', "\r\n");
+            prop_assert_eq!(decode(crlf.trim_end().as_bytes()).unwrap(), input.clone());
+
+            // older files are bare base64, and pasted ones may be rewrapped anyhow
+            let bare: Vec<u8> = base64::encode(&input)
+                .as_bytes()
+                .chunks(wrap)
+                .flat_map(|line| line.iter().copied().chain(*b" \n"))
+                .collect();
+            prop_assert_eq!(decode(&bare).unwrap(), input.clone());
+
+            let truncated = &armored[..armored.len() - 10];
+            prop_assert!(decode(truncated).is_err());
+        }
+    }
+}
@@ -0,0 +1,233 @@
+// Compression of the plaintext before it's encrypted. The algorithm is recorded in the header
+// flags, so versions that don't know about it refuse the file instead of returning compressed
+// data.
+
+use std::io::{self, Read, Write};
+
+use clap::ValueEnum;
+use color_eyre::eyre::{self, bail};
+use flate2::{read::DeflateEncoder, write::DeflateDecoder};
+use zstd::stream::raw::Operation as _;
+
+use crate::envelope::{FLAG_DEFLATE, FLAG_ZSTD};
+
+// so a small file can't fill up the memory or the tmpfs
+const MAX_DECOMPRESSED_LEN: u64 = 1024 * 1024 * 1024;
+// what zstd suggests (`ZSTD_DStreamOutSize`), a full block
+const ZSTD_OUTPUT_LEN: usize = 128 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    Zstd,
+    Deflate,
+}
+
+impl Compression {
+    pub fn flag(self) -> u16 {
+        match self {
+            Self::Zstd => FLAG_ZSTD,
+            Self::Deflate => FLAG_DEFLATE,
+        }
+    }
+
+    pub fn from_flags(flags: u16) -> eyre::Result<Option<Self>> {
+        match (flags & FLAG_ZSTD != 0, flags & FLAG_DEFLATE != 0) {
+            (false, false) => Ok(None),
+            (true, false) => Ok(Some(Self::Zstd)),
+            (false, true) => Ok(Some(Self::Deflate)),
+            (true, true) => bail!("file is marked as compressed with both zstd and deflate"),
+        }
+    }
+}
+
+pub fn compress<'a>(
+    compression: Option<Compression>,
+    input: impl Read + 'a,
+) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        None => Box::new(input),
+        Some(Compression::Zstd) => Box::new(zstd::stream::read::Encoder::new(input, 0)?),
+        Some(Compression::Deflate) => {
+            Box::new(DeflateEncoder::new(input, flate2::Compression::default()))
+        }
+    })
+}
+
+// Fails instead of writing more than `MAX_DECOMPRESSED_LEN` bytes
+pub struct Limited<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W> Limited<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, written: 0 }
+    }
+}
+
+impl<W: Write> Write for Limited<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written + buf.len() as u64 > MAX_DECOMPRESSED_LEN {
+            return Err(io::Error::other("decompressed data is larger than 1 GiB"));
+        }
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// zstd's own writer doesn't notice when the input ends in the middle of a frame, so a truncated
+// file would decompress to truncated data without an error
+pub struct ZstdDecoder<W: Write> {
+    decoder: zstd::stream::raw::Decoder<'static>,
+    output: Limited<W>,
+    buffer: Vec<u8>,
+    // zstd's hint for the next input, 0 at the end of a frame
+    remaining: usize,
+}
+
+impl<W: Write> ZstdDecoder<W> {
+    fn new(output: W) -> io::Result<Self> {
+        Ok(Self {
+            decoder: zstd::stream::raw::Decoder::new()?,
+            output: Limited::new(output),
+            buffer: vec![0; ZSTD_OUTPUT_LEN],
+            // no frame at all is incomplete too
+            remaining: 1,
+        })
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if self.remaining != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "compressed data ends in the middle of a zstd frame",
+            ));
+        }
+        self.output.flush()
+    }
+}
+
+impl<W: Write> Write for ZstdDecoder<W> {
+    // Consumes all of `buf`, and writes out everything decompressed from it
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len();
+        loop {
+            let status = self.decoder.run_on_buffers(buf, &mut self.buffer)?;
+            self.remaining = status.remaining;
+            self.output
+                .write_all(&self.buffer[..status.bytes_written])?;
+            buf = &buf[status.bytes_read..];
+            // a full buffer means there may be more output without more input, unless the frame
+            // ended, which zstd only reports once all of it is written
+            if buf.is_empty() && (status.remaining == 0 || status.bytes_written < self.buffer.len())
+            {
+                return Ok(len);
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+// Writer that decompresses everything written to it
+pub enum Decompressor<W: Write> {
+    Plain(W),
+    Zstd(ZstdDecoder<W>),
+    Deflate(DeflateDecoder<Limited<W>>),
+}
+
+impl<W: Write> Decompressor<W> {
+    pub fn new(compression: Option<Compression>, output: W) -> io::Result<Self> {
+        Ok(match compression {
+            None => Self::Plain(output),
+            Some(Compression::Zstd) => Self::Zstd(ZstdDecoder::new(output)?),
+            Some(Compression::Deflate) => Self::Deflate(DeflateDecoder::new(Limited::new(output))),
+        })
+    }
+
+    // Writes out whatever is still buffered, failing if the compressed data is truncated
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Plain(mut output) => output.flush(),
+            Self::Zstd(decoder) => decoder.finish(),
+            Self::Deflate(decoder) => decoder.finish()?.flush(),
+        }
+    }
+}
+
+impl<W: Write> Write for Decompressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(output) => output.write(buf),
+            Self::Zstd(decoder) => decoder.write(buf),
+            Self::Deflate(decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(output) => output.flush(),
+            Self::Zstd(decoder) => decoder.flush(),
+            Self::Deflate(decoder) => decoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::io::{Read as _, Write as _};
+
+    use proptest::{collection::vec, prop_assert_eq, proptest};
+
+    use super::{compress, Compression, Decompressor};
+
+    proptest! {
+        #[test]
+        fn roundtrip(input in vec(0..=u8::MAX, 0..4096)) {
+            for compression in [None, Some(Compression::Zstd), Some(Compression::Deflate)] {
+                let mut compressed = Vec::new();
+                compress(compression, input.as_slice())
+                    .unwrap()
+                    .read_to_end(&mut compressed)
+                    .unwrap();
+                let mut decompressed = Vec::new();
+                let mut decompressor = Decompressor::new(compression, &mut decompressed).unwrap();
+                decompressor.write_all(&compressed).unwrap();
+                decompressor.finish().unwrap();
+                prop_assert_eq!(&decompressed, &input);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_zstd() {
+        let input = vec![7_u8; 1 << 20];
+        let mut compressed = Vec::new();
+        compress(Some(Compression::Zstd), input.as_slice())
+            .unwrap()
+            .read_to_end(&mut compressed)
+            .unwrap();
+        for len in [0, compressed.len() / 2, compressed.len() - 1] {
+            let mut decompressor = Decompressor::new(Some(Compression::Zstd), Vec::new()).unwrap();
+            decompressor.write_all(&compressed[..len]).unwrap();
+            assert!(decompressor.finish().is_err(), "{len}");
+        }
+
+        // frames can be concatenated
+        let mut decompressed = Vec::new();
+        let mut decompressor =
+            Decompressor::new(Some(Compression::Zstd), &mut decompressed).unwrap();
+        decompressor.write_all(&compressed).unwrap();
+        decompressor.write_all(&compressed).unwrap();
+        decompressor.finish().unwrap();
+        assert_eq!(decompressed.len(), 2 * input.len());
+    }
+}
@@ -1,7 +1,6 @@
 use std::{
     collections::HashMap,
     fs::{self, OpenOptions},
-    io::Write,
     mem,
     os::unix::{fs::OpenOptionsExt as _, io::AsRawFd as _},
     path::{Path, PathBuf},
@@ -16,6 +15,8 @@ use nix::{
 };
 use serde::{de::Error as _, Deserialize, Deserializer};
 
+use crate::{keysource::KeySource, sign};
+
 fn default_mode() -> u32 {
     0o400
 }
@@ -48,9 +49,27 @@ fn deserialize_mode<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D
     }
 }
 
+// A key name or an ordered list of them, `null` for none
+fn deserialize_keys<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Helper {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    match Option::<Helper>::deserialize(deserializer)? {
+        None => Ok(Vec::new()),
+        Some(Helper::One(key)) => Ok(vec![key]),
+        Some(Helper::Many(keys)) => Ok(keys),
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct FileDesc {
-    pub key: Option<String>,
+    // keys after the first one are deprecated, the file should be rekeyed
+    #[serde(default, deserialize_with = "deserialize_keys")]
+    pub key: Vec<String>,
     #[serde(default)]
     pub encrypted: PathBuf,
     #[serde(default = "default_mode", deserialize_with = "deserialize_mode")]
@@ -99,7 +118,11 @@ impl FileDesc {
     }
 
     #[allow(clippy::similar_names)]
-    pub fn create(&self, path: &Path, contents: &[u8]) -> eyre::Result<()> {
+    pub fn create(
+        &self,
+        path: &Path,
+        write: impl FnOnce(&mut fs::File) -> eyre::Result<()>,
+    ) -> eyre::Result<()> {
         let uid = self.uid()?;
         let gid = self.gid()?;
 
@@ -112,8 +135,7 @@ impl FileDesc {
             .wrap_err("failed to open decrypted file")?;
         let guard = Defer(|| drop(fs::remove_file(path)));
 
-        file.write_all(contents)
-            .wrap_err("failed to write to decrypted file")?;
+        write(&mut file)?;
 
         let fd = file.as_raw_fd();
         fchmod(fd, Mode::empty()).wrap_err("failed to chmod file")?;
@@ -129,12 +151,27 @@ impl FileDesc {
 pub struct Config {
     #[serde(alias = "targetDir")]
     pub target_dir: PathBuf,
-    pub keys: IndexMap<String, PathBuf>,
+    pub keys: IndexMap<String, KeySource>,
+    // like `FileDesc::key`, for files that don't specify one
+    #[serde(default, alias = "defaultKey", deserialize_with = "deserialize_keys")]
+    pub default_key: Vec<String>,
+    // if not empty, every file must be signed by one of these
+    #[serde(default, alias = "trustedSigners")]
+    pub trusted_signers: Vec<sign::PublicKey>,
     #[serde(default)]
     pub files: HashMap<String, FileDesc>,
 }
 
 impl Config {
+    // Names of the keys to try for `file`, in order
+    pub fn keys_for<'a>(&'a self, file: &'a FileDesc) -> &'a [String] {
+        if file.key.is_empty() {
+            &self.default_key
+        } else {
+            &file.key
+        }
+    }
+
     pub fn parse(contents: &[u8]) -> eyre::Result<Self> {
         let mb_json = serde_json::from_slice(contents);
         let mb_toml = toml::from_slice(contents);
@@ -152,3 +189,45 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::Config;
+
+    fn parse(default_key: &str, key: &str) -> Config {
+        let config = format!(
+            r#"{{
+                "target_dir": "/var/secrets",
+                "keys": {{"new": "/new.key", "old": "/old.key"}},
+                {default_key}
+                "files": {{"secret": {{"encrypted": "/secret.enc" {key}}}}}
+            }}"#
+        );
+        Config::parse(config.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn keys() {
+        for (default_key, key, expected) in [
+            ("", "", vec![]),
+            ("", r#", "key": null"#, vec![]),
+            ("", r#", "key": "old""#, vec!["old"]),
+            ("", r#", "key": ["new", "old"]"#, vec!["new", "old"]),
+            (r#""defaultKey": "new","#, "", vec!["new"]),
+            (r#""default_key": ["new", "old"],"#, "", vec!["new", "old"]),
+            (r#""defaultKey": null,"#, "", vec![]),
+            // the file's own keys win
+            (
+                r#""defaultKey": ["new", "old"],"#,
+                r#", "key": "old""#,
+                vec!["old"],
+            ),
+        ] {
+            let config = parse(default_key, key);
+            let file = &config.files["secret"];
+            assert_eq!(config.keys_for(file), expected, "{default_key} {key}");
+        }
+        assert!(Config::parse(br#"{"target_dir": "/", "keys": {}, "defaultKey": 1}"#).is_err());
+    }
+}
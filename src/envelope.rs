@@ -0,0 +1,303 @@
+// Layout of an encrypted file:
+//
+//   magic (4 bytes) | version (1) | algorithm (1) | flags (2, BE) | body length (4, BE)
+//   | CBOR-encoded `Body` | payload
+//
+// Everything before the payload is authenticated as associated data. The payload is either a
+// single AEAD message or, with `FLAG_STREAM`, a sequence of chunks (see `stream.rs`). With
+// `FLAG_SIGNED`, a signature over everything before it follows the payload.
+// Files without the magic are legacy ones: a bare CBOR map with `nonce` and `bytes`.
+
+use std::io::{self, Read};
+
+use chacha20poly1305::{aead::Nonce, XChaCha20Poly1305};
+use clap::ValueEnum;
+use color_eyre::eyre::{self, bail, ensure, WrapErr as _};
+use either::Either;
+use serde::{Deserialize, Serialize};
+
+pub const MAGIC: &[u8; 4] = b"clsf";
+// files are armored as text by default
+pub const ARMOR_LABEL: &str = "CLASSIFIED";
+const HEADER_LEN: usize = 12;
+const MAX_BODY_LEN: u32 = 1024 * 1024;
+
+pub const FLAG_STREAM: u16 = 0x0001;
+// the plaintext is compressed, see `compress.rs`
+pub const FLAG_ZSTD: u16 = 0x0002;
+pub const FLAG_DEFLATE: u16 = 0x0004;
+// the plaintext is padded, see `pad.rs`
+pub const FLAG_PADDED: u16 = 0x0008;
+// the payload is followed by a signature, see `sign.rs`
+pub const FLAG_SIGNED: u16 = 0x0010;
+const KNOWN_FLAGS: u16 = FLAG_STREAM | FLAG_ZSTD | FLAG_DEFLATE | FLAG_PADDED | FLAG_SIGNED;
+
+pub const LEGACY_VERSION: u8 = 0;
+pub const VERSION: u8 = 1;
+
+// Both take the same 32-byte key
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Algorithm {
+    /// The default, with nonces long enough to be chosen at random
+    #[value(name = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+    /// Nonce-misuse resistant: a repeated nonce only reveals whether two messages are equal
+    #[value(name = "aes-256-gcm-siv")]
+    Aes256GcmSiv,
+}
+
+impl Algorithm {
+    fn id(self) -> u8 {
+        match self {
+            Self::XChaCha20Poly1305 => 1,
+            Self::Aes256GcmSiv => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> eyre::Result<Self> {
+        match id {
+            1 => Ok(Self::XChaCha20Poly1305),
+            2 => Ok(Self::Aes256GcmSiv),
+            _ => bail!("unknown encryption algorithm id {id}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub algorithm: Algorithm,
+    pub flags: u16,
+}
+
+impl Header {
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            version: VERSION,
+            algorithm,
+            flags: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Body {
+    // nonce, or its prefix in streaming mode
+    #[serde(with = "serde_bytes")]
+    pub nonce: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u32>,
+    // what the secret is for, e.g. its name in the config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    // `KeyId` of the key the file was encrypted with
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    pub key_id: Option<Vec<u8>>,
+    // if not empty, the payload is encrypted with a random data key, wrapped for every recipient
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<Recipient>,
+    // if set, the payload key is derived from this salt, the unlocked key and the context
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    pub salt: Option<Vec<u8>>,
+    // Ed25519 public key of whoever signed the file, with `FLAG_SIGNED`
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    pub signer: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Recipient {
+    // data key encrypted with a symmetric key
+    Key {
+        #[serde(with = "serde_bytes")]
+        key_id: Vec<u8>,
+        #[serde(with = "serde_bytes")]
+        nonce: Vec<u8>,
+        #[serde(with = "serde_bytes")]
+        wrapped: Vec<u8>,
+    },
+    // data key encrypted for an X25519 public key, see `x25519.rs`
+    X25519 {
+        #[serde(with = "serde_bytes")]
+        ephemeral: Vec<u8>,
+        #[serde(with = "serde_bytes")]
+        wrapped: Vec<u8>,
+    },
+}
+
+#[derive(Deserialize)]
+struct Legacy {
+    nonce: Nonce<XChaCha20Poly1305>,
+    bytes: Vec<u8>,
+}
+
+// Rest of the file after the envelope. Legacy files are read completely.
+pub type Payload<R> = Either<R, io::Cursor<Vec<u8>>>;
+
+pub struct Envelope {
+    pub header: Header,
+    pub body: Body,
+    // header and body exactly as they were written
+    pub associated_data: Vec<u8>,
+}
+
+impl Envelope {
+    pub fn new(header: Header, body: Body) -> eyre::Result<Self> {
+        let body_bytes = serde_cbor::to_vec(&body).wrap_err("failed to serialize envelope body")?;
+        let body_len = u32::try_from(body_bytes.len()).wrap_err("envelope body is too long")?;
+
+        let mut associated_data = Vec::with_capacity(HEADER_LEN + body_bytes.len());
+        associated_data.extend_from_slice(MAGIC);
+        associated_data.push(header.version);
+        associated_data.push(header.algorithm.id());
+        associated_data.extend_from_slice(&header.flags.to_be_bytes());
+        associated_data.extend_from_slice(&body_len.to_be_bytes());
+        associated_data.extend_from_slice(&body_bytes);
+        Ok(Self {
+            header,
+            body,
+            associated_data,
+        })
+    }
+
+    pub fn is_stream(&self) -> bool {
+        self.header.flags & FLAG_STREAM != 0
+    }
+
+    // Files without a context label are accepted anywhere
+    pub fn check_context(&self, expected: &str) -> eyre::Result<()> {
+        match &self.body.context {
+            Some(context) if context != expected => {
+                bail!("file was encrypted for {context:?}, not {expected:?}")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Read header and body, returning the reader positioned at the start of the payload.
+    pub fn read<R: Read>(mut reader: R) -> eyre::Result<(Self, Payload<R>)> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        reader
+            .by_ref()
+            .take(MAGIC.len() as u64)
+            .read_to_end(&mut header)
+            .wrap_err("failed to read encrypted file")?;
+        if header != MAGIC {
+            reader
+                .read_to_end(&mut header)
+                .wrap_err("failed to read encrypted file")?;
+            let (envelope, payload) = Self::parse_legacy(&header)?;
+            return Ok((envelope, Either::Right(io::Cursor::new(payload))));
+        }
+
+        header.resize(HEADER_LEN, 0);
+        reader
+            .read_exact(&mut header[MAGIC.len()..])
+            .wrap_err("encrypted file header is truncated")?;
+        let version = header[4];
+        ensure!(
+            version == VERSION,
+            "unsupported encrypted file version {version}, this build supports up to {VERSION}"
+        );
+        let algorithm = Algorithm::from_id(header[5])?;
+        let flags = u16::from_be_bytes([header[6], header[7]]);
+        ensure!(
+            flags & !KNOWN_FLAGS == 0,
+            "encrypted file uses unsupported flags {flags:#06x}"
+        );
+        let body_len = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        ensure!(body_len <= MAX_BODY_LEN, "encrypted file body is too long");
+
+        let mut associated_data = header;
+        associated_data.resize(HEADER_LEN + body_len as usize, 0);
+        reader
+            .read_exact(&mut associated_data[HEADER_LEN..])
+            .wrap_err("encrypted file body is truncated")?;
+        let body = serde_cbor::from_slice(&associated_data[HEADER_LEN..])
+            .wrap_err("failed to deserialize envelope body")?;
+
+        let envelope = Self {
+            header: Header {
+                version,
+                algorithm,
+                flags,
+            },
+            body,
+            associated_data,
+        };
+        Ok((envelope, Either::Left(reader)))
+    }
+
+    fn parse_legacy(bytes: &[u8]) -> eyre::Result<(Self, Vec<u8>)> {
+        let legacy: Legacy =
+            serde_cbor::from_slice(bytes).wrap_err("failed to deserialize encrypted file")?;
+        let envelope = Self {
+            header: Header {
+                version: LEGACY_VERSION,
+                algorithm: Algorithm::XChaCha20Poly1305,
+                flags: 0,
+            },
+            body: Body {
+                nonce: legacy.nonce.to_vec(),
+                ..Body::default()
+            },
+            associated_data: Vec::new(),
+        };
+        Ok((envelope, legacy.bytes))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::io::Read as _;
+
+    use super::{Algorithm, Body, Envelope, Header, LEGACY_VERSION, VERSION};
+    use proptest::{collection::vec, prop_assert_eq, proptest};
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Legacy {
+        nonce: [u8; 24],
+        bytes: Vec<u8>,
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip(
+            nonce in vec(0..=u8::MAX, 24),
+            context in proptest::option::of(".*"),
+            payload in vec(0..=u8::MAX, 0..256),
+        ) {
+            let header = Header::new(Algorithm::XChaCha20Poly1305);
+            let body = Body { nonce: nonce.clone(), context: context.clone(), ..Body::default() };
+            let envelope = Envelope::new(header, body).unwrap();
+            let mut bytes = envelope.associated_data.clone();
+            bytes.extend_from_slice(&payload);
+
+            let (parsed, mut rest) = Envelope::read(bytes.as_slice()).unwrap();
+            let mut parsed_payload = Vec::new();
+            rest.read_to_end(&mut parsed_payload).unwrap();
+            prop_assert_eq!(parsed.header, header);
+            prop_assert_eq!(parsed.header.version, VERSION);
+            prop_assert_eq!(parsed.body.nonce, nonce);
+            prop_assert_eq!(parsed.body.context, context);
+            prop_assert_eq!(parsed.associated_data, envelope.associated_data);
+            prop_assert_eq!(parsed_payload, payload);
+        }
+
+        #[test]
+        fn legacy(nonce in vec(0..=u8::MAX, 24), bytes in vec(0..=u8::MAX, 0..256)) {
+            let nonce = <[u8; 24]>::try_from(nonce).unwrap();
+            let cbor = serde_cbor::to_vec(&Legacy { nonce, bytes: bytes.clone() }).unwrap();
+
+            let (parsed, mut rest) = Envelope::read(cbor.as_slice()).unwrap();
+            let mut payload = Vec::new();
+            rest.read_to_end(&mut payload).unwrap();
+            prop_assert_eq!(parsed.header.version, LEGACY_VERSION);
+            prop_assert_eq!(parsed.body.nonce, nonce);
+            prop_assert_eq!(parsed.associated_data, Vec::<u8>::new());
+            prop_assert_eq!(payload, bytes);
+        }
+    }
+}
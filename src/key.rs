@@ -0,0 +1,729 @@
+use std::{
+    fmt::{self, Write as _},
+    ops::Deref,
+    slice,
+    str::FromStr,
+};
+
+use chacha20poly1305::{
+    aead::{Aead as _, Key, Nonce},
+    AeadCore as _, KeyInit as _, XChaCha20Poly1305 as Cipher,
+};
+use clap::ValueEnum;
+use color_eyre::eyre::{self, bail, ensure, eyre, WrapErr as _};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use indexmap::IndexMap;
+use itertools::Itertools as _;
+use rand::{CryptoRng, RngCore};
+use sha2::Sha256;
+use x25519_dalek::StaticSecret;
+use zeroize::{Zeroize as _, Zeroizing};
+
+use crate::{
+    age,
+    envelope::{Body, Envelope, Recipient},
+    keyarmor,
+    keysource::KeySource,
+    passphrase::{self, Passphrase},
+    ssh, x25519,
+};
+
+const SALT_LEN: usize = 32;
+const KEY_LEN: usize = 32;
+
+// Non-secret identifier of a key, stored in encrypted files
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct KeyId([u8; 8]);
+
+impl KeyId {
+    pub fn from_slice(bytes: &[u8]) -> eyre::Result<Self> {
+        Ok(Self(bytes.try_into().wrap_err("wrong key id length")?))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for KeyId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for KeyId {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        ensure!(
+            s.len() == 16 && s.bytes().all(|byte| byte.is_ascii_hexdigit()),
+            "key id should be 16 hex digits"
+        );
+        let mut id = [0_u8; 8];
+        for (byte, i) in id.iter_mut().zip((0..16).step_by(2)) {
+            *byte = u8::from_str_radix(&s[i..i + 2], 16).wrap_err("malformed key id")?;
+        }
+        Ok(Self(id))
+    }
+}
+
+pub fn generate_salt(mut rng: impl RngCore + CryptoRng) -> Vec<u8> {
+    let mut salt = vec![0; SALT_LEN];
+    rng.fill_bytes(&mut salt);
+    salt
+}
+
+// How a key is written in a key file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum KeyFormat {
+    // words from `keyarmor`, which can be written down
+    #[default]
+    Words,
+    Hex,
+    Base64,
+    // just the 32 bytes, without a header
+    Raw,
+}
+
+impl fmt::Display for KeyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no key format is skipped");
+        write!(f, "{}", value.get_name())
+    }
+}
+
+// `# name: value` lines at the start of a key file, which aren't part of the key
+fn split_header(contents: &str) -> (Vec<(String, String)>, &str) {
+    let mut header = Vec::new();
+    let mut body = contents;
+    for line in contents.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if !trimmed.is_empty() {
+            let Some(comment) = trimmed.strip_prefix('#') else {
+                break;
+            };
+            if let Some((name, value)) = comment.split_once(':') {
+                header.push((name.trim().to_owned(), value.trim().to_owned()));
+            }
+        }
+        body = &body[line.len()..];
+    }
+    (header, body)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() != KEY_LEN * 2 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+pub struct ArmoredKey {
+    inner: Key<Cipher>,
+    // X25519 secret of a key imported from another tool, e.g. an age identity
+    imported: Option<StaticSecret>,
+    // set for keys converted from SSH keys, which age treats differently
+    ssh: Option<ssh::PublicKey>,
+    // so the key is written back with the checksum and words it was read with
+    checksum: keyarmor::Checksum,
+    wordlist: keyarmor::Wordlist,
+    format: KeyFormat,
+    header: Vec<(String, String)>,
+}
+
+impl fmt::Display for ArmoredKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // SSH keys can't be converted back, but their X25519 secret is enough for decryption
+        if let Some(secret) = &self.imported {
+            return write!(f, "{}", age::format_identity(secret));
+        }
+        write!(
+            f,
+            "{}",
+            keyarmor::Words::new(&self.inner, self.checksum, self.wordlist)
+                .expect("keys can be armored with any checksum")
+        )
+    }
+}
+
+impl ArmoredKey {
+    pub fn new(inner: Key<Cipher>) -> Self {
+        Self {
+            inner,
+            imported: None,
+            ssh: None,
+            checksum: keyarmor::Checksum::Crc8,
+            wordlist: keyarmor::Wordlist::English,
+            format: KeyFormat::Words,
+            header: Vec::new(),
+        }
+    }
+
+    pub fn with_checksum(mut self, checksum: keyarmor::Checksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn with_wordlist(mut self, wordlist: keyarmor::Wordlist) -> Self {
+        self.wordlist = wordlist;
+        self
+    }
+
+    pub fn with_format(mut self, format: KeyFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.header.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    // The symmetric key is derived from the secret, so imported keys can be used everywhere
+    fn from_secret(secret: StaticSecret, ssh: Option<ssh::PublicKey>) -> Self {
+        let mut inner = Key::<Cipher>::default();
+        Hkdf::<Sha256>::new(None, secret.as_bytes())
+            .expand(b"classified imported key", &mut inner)
+            .expect("32 bytes is a valid HKDF output length");
+        Self {
+            inner,
+            imported: Some(secret),
+            ssh,
+            checksum: keyarmor::Checksum::Crc8,
+            wordlist: keyarmor::Wordlist::English,
+            format: KeyFormat::Words,
+            header: Vec::new(),
+        }
+    }
+
+    pub fn load(source: &KeySource, passphrase: &mut Passphrase) -> eyre::Result<Self> {
+        passphrase::read_key_file(source, passphrase)
+            .and_then(|contents| Self::parse(&contents))
+            .wrap_err_with(|| format!("failed to load key from {source}"))
+    }
+
+    // Contents of a key file: the 32 bytes of the key, or text with an optional header of
+    // `# name: value` lines, followed by the key as words from any of the lists, hex or base64,
+    // an age identity or an OpenSSH private key
+    pub fn parse(contents: &[u8]) -> eyre::Result<Self> {
+        if contents.len() == KEY_LEN {
+            return Ok(
+                Self::new(Key::<Cipher>::clone_from_slice(contents)).with_format(KeyFormat::Raw)
+            );
+        }
+        let contents = std::str::from_utf8(contents)
+            .map_err(|_| eyre!("key file is neither text nor {KEY_LEN} raw bytes"))?;
+        let (header, body) = split_header(contents);
+        let mut key = Self::parse_body(body)?;
+        key.header = header;
+        Ok(key)
+    }
+
+    fn parse_body(body: &str) -> eyre::Result<Self> {
+        if let Some(secret) = age::parse_identity(body)? {
+            return Ok(Self::from_secret(secret, None));
+        }
+        if let Some((secret, public)) = ssh::parse_private_key(body)? {
+            return Ok(Self::from_secret(secret, Some(public)));
+        }
+        let encoded = body.trim();
+        if let Some(mut bytes) = decode_hex(encoded) {
+            let key =
+                Self::new(Key::<Cipher>::clone_from_slice(&bytes)).with_format(KeyFormat::Hex);
+            bytes.zeroize();
+            return Ok(key);
+        }
+        if let Ok(mut bytes) = base64::decode(encoded) {
+            let key = (bytes.len() == KEY_LEN).then(|| {
+                Self::new(Key::<Cipher>::clone_from_slice(&bytes)).with_format(KeyFormat::Base64)
+            });
+            bytes.zeroize();
+            if let Some(key) = key {
+                return Ok(key);
+            }
+        }
+        let words: Vec<_> = body.split_whitespace().collect();
+        ensure!(
+            words.len() > 1,
+            "not a key: it's neither words, {} hex digits nor base64 of {KEY_LEN} bytes",
+            KEY_LEN * 2
+        );
+        let words = keyarmor::Words::from_key_words(&words).wrap_err("failed to decode key")?;
+        Ok(Self::new(Key::<Cipher>::clone_from_slice(words.bytes()))
+            .with_checksum(words.checksum())
+            .with_wordlist(words.wordlist()))
+    }
+
+    // Contents of a key file with this key, in its format
+    pub fn encode(&self) -> Zeroizing<Vec<u8>> {
+        if self.imported.is_none() && self.format == KeyFormat::Raw {
+            return Zeroizing::new(self.inner.to_vec());
+        }
+        let mut contents = Zeroizing::new(String::new());
+        for (name, value) in &self.header {
+            writeln!(contents, "# {name}: {value}").expect("writing to a string can't fail");
+        }
+        match self.format {
+            _ if self.imported.is_some() => writeln!(contents, "{self}"),
+            KeyFormat::Words | KeyFormat::Raw => writeln!(contents, "{self}"),
+            KeyFormat::Hex => writeln!(
+                contents,
+                "{}",
+                self.inner
+                    .iter()
+                    .format_with("", |byte, f| f(&format_args!("{byte:02x}")))
+            ),
+            KeyFormat::Base64 => {
+                writeln!(contents, "{}", *Zeroizing::new(base64::encode(self.inner)))
+            }
+        }
+        .expect("writing to a string can't fail");
+        Zeroizing::new(std::mem::take(&mut *contents).into_bytes())
+    }
+
+    pub fn imported_secret(&self) -> Option<&StaticSecret> {
+        self.imported.as_ref()
+    }
+
+    pub fn ssh_public(&self) -> Option<&ssh::PublicKey> {
+        self.ssh.as_ref()
+    }
+
+    // `None` for imported keys, which have their own format
+    pub fn format(&self) -> Option<KeyFormat> {
+        self.imported.is_none().then_some(self.format)
+    }
+
+    pub fn header(&self) -> &[(String, String)] {
+        &self.header
+    }
+
+    // `None` for keys that aren't words
+    pub fn checksum(&self) -> Option<keyarmor::Checksum> {
+        (self.format() == Some(KeyFormat::Words)).then_some(self.checksum)
+    }
+
+    pub fn wordlist(&self) -> Option<keyarmor::Wordlist> {
+        (self.format() == Some(KeyFormat::Words)).then_some(self.wordlist)
+    }
+
+    pub fn wrap(
+        &self,
+        data_key: &Key<Cipher>,
+        rng: impl RngCore + CryptoRng,
+    ) -> eyre::Result<Recipient> {
+        let nonce = Cipher::generate_nonce(rng);
+        let wrapped = Cipher::new(&self.inner)
+            .encrypt(&nonce, data_key.as_slice())
+            .map_err(|_| eyre!("failed to wrap data key"))?;
+        Ok(Recipient::Key {
+            key_id: self.id().as_bytes().to_vec(),
+            nonce: nonce.to_vec(),
+            wrapped,
+        })
+    }
+
+    // Returns `None` if the data key is not wrapped for this key
+    fn unwrap(&self, recipients: &[Recipient]) -> eyre::Result<Option<ArmoredKey>> {
+        let id = self.id();
+        for recipient in recipients {
+            let (key_id, nonce, wrapped) = match recipient {
+                Recipient::Key {
+                    key_id,
+                    nonce,
+                    wrapped,
+                } => (key_id, nonce, wrapped),
+                Recipient::X25519 { ephemeral, wrapped } => {
+                    match x25519::unwrap(self, ephemeral, wrapped)? {
+                        Some(data_key) => return Ok(Some(data_key)),
+                        None => continue,
+                    }
+                }
+            };
+            if key_id.as_slice() != id.as_bytes() {
+                continue;
+            }
+
+            ensure!(
+                nonce.len() == Nonce::<Cipher>::default().len(),
+                "wrong nonce length"
+            );
+            let mut data_key = Cipher::new(&self.inner)
+                .decrypt(Nonce::<Cipher>::from_slice(nonce), wrapped.as_slice())
+                .map_err(|_| eyre!("wrapped key is corrupted"))?;
+            let res = Key::<Cipher>::from_exact_iter(data_key.iter().copied())
+                .ok_or_else(|| eyre!("wrong data key length"));
+            data_key.zeroize();
+            return res.map(|key| Some(ArmoredKey::new(key)));
+        }
+        Ok(None)
+    }
+
+    // Key for the payload of a single file, so no two files are encrypted with the same key and
+    // secrets with different contexts are separated. Files without a salt use the key itself.
+    pub fn file_key(&self, body: &Body) -> eyre::Result<ArmoredKey> {
+        let Some(salt) = &body.salt else {
+            return Ok(ArmoredKey::new(self.inner));
+        };
+        ensure!(salt.len() == SALT_LEN, "wrong salt length");
+        let context = body.context.as_deref().unwrap_or_default();
+        let mut inner = Key::<Cipher>::default();
+        Hkdf::<Sha256>::new(Some(salt), &self.inner)
+            .expand_multi_info(&[b"classified file key", context.as_bytes()], &mut inner)
+            .expect("32 bytes is a valid HKDF output length");
+        Ok(ArmoredKey::new(inner))
+    }
+
+    pub fn id(&self) -> KeyId {
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(&self.inner).expect("HMAC accepts any key");
+        mac.update(b"classified key id");
+        let mut id = [0_u8; 8];
+        id.copy_from_slice(&mac.finalize().into_bytes()[..8]);
+        KeyId(id)
+    }
+}
+
+impl Deref for ArmoredKey {
+    type Target = Key<Cipher>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl Drop for ArmoredKey {
+    fn drop(&mut self) {
+        self.inner.zeroize();
+    }
+}
+
+// Named keys to choose from when decrypting
+pub struct Keyring {
+    keys: IndexMap<String, ArmoredKey>,
+}
+
+impl Keyring {
+    pub fn new(keys: IndexMap<String, ArmoredKey>) -> Self {
+        Self { keys }
+    }
+
+    fn get(&self, name: &str) -> eyre::Result<&ArmoredKey> {
+        self.keys
+            .get(name)
+            .ok_or_else(|| eyre!("key {name:?} is not configured"))
+    }
+
+    fn describe(&self, id: KeyId) -> String {
+        match self.keys.iter().find(|(_, key)| key.id() == id) {
+            Some((name, _)) => format!("key {name:?}"),
+            None => format!("unknown key {id}"),
+        }
+    }
+
+    /// Find the key the payload of `envelope` is encrypted with: using the key named `wanted` if
+    /// it's given, otherwise the one the file was encrypted for, otherwise the first one. Files
+    /// without a key ID can't be checked here, only by decrypting them with each candidate.
+    pub fn unlock(&self, wanted: Option<&str>, envelope: &Envelope) -> eyre::Result<ArmoredKey> {
+        self.data_key(wanted, envelope)?.file_key(&envelope.body)
+    }
+
+    /// Like `unlock`, but returns the key before the per-file key is derived from it: the
+    /// unwrapped data key, or the configured key itself
+    pub fn data_key(&self, wanted: Option<&str>, envelope: &Envelope) -> eyre::Result<ArmoredKey> {
+        if !envelope.body.recipients.is_empty() {
+            return self.unwrap(wanted, &envelope.body.recipients);
+        }
+
+        let id = envelope
+            .body
+            .key_id
+            .as_deref()
+            .map(KeyId::from_slice)
+            .transpose()?;
+        let key = match (wanted, id) {
+            (Some(wanted), Some(id)) => {
+                let key = self.get(wanted)?;
+                ensure!(
+                    key.id() == id,
+                    "file is encrypted for {}, but entry says {wanted:?}",
+                    self.describe(id),
+                );
+                key
+            }
+            (Some(wanted), None) => self.get(wanted)?,
+            (None, Some(id)) => self
+                .keys
+                .values()
+                .find(|key| key.id() == id)
+                .ok_or_else(|| eyre!("file is encrypted for key {id}, which is not configured"))?,
+            (None, None) => self
+                .keys
+                .values()
+                .next()
+                .ok_or_else(|| eyre!("no keys specified"))?,
+        };
+        Ok(ArmoredKey::new(key.inner))
+    }
+
+    /// Keys to try when the file doesn't say which one it's encrypted for: the one named `wanted`
+    /// if it's given, otherwise all of them.
+    pub fn candidates<'a>(
+        &'a self,
+        wanted: Option<&'a str>,
+    ) -> eyre::Result<Vec<(&'a str, &'a ArmoredKey)>> {
+        match wanted {
+            Some(wanted) => Ok(vec![(wanted, self.get(wanted)?)]),
+            None => Ok(self
+                .keys
+                .iter()
+                .map(|(name, key)| (name.as_str(), key))
+                .collect()),
+        }
+    }
+
+    /// Recipients for a new `data_key` replacing `recipients`: `to` and the public keys in
+    /// `public`. Entries of keys from the keyring (and of `to`) are dropped. Other keys can't be
+    /// given the new data key without their secret, so they're an error, and other public keys
+    /// have to be in `public`, since their entries don't say whose they are.
+    pub fn rewrap(
+        &self,
+        recipients: &[Recipient],
+        data_key: &Key<Cipher>,
+        to: &ArmoredKey,
+        public: &[x25519::PublicKey],
+        mut rng: impl RngCore + CryptoRng,
+    ) -> eyre::Result<Vec<Recipient>> {
+        let mut others = 0;
+        for recipient in recipients {
+            let mut owned = false;
+            for key in self.keys.values().chain([to]) {
+                if key.unwrap(slice::from_ref(recipient))?.is_some() {
+                    owned = true;
+                    break;
+                }
+            }
+            match recipient {
+                _ if owned => {}
+                Recipient::Key { key_id, .. } => bail!(
+                    "file is also encrypted for {}, which can't be given the new data key without \
+                     its secret: rekey from it too to drop it, or encrypt the file again",
+                    KeyId::from_slice(key_id)
+                        .map_or_else(|_| "a malformed key id".to_owned(), |id| self.describe(id)),
+                ),
+                Recipient::X25519 { .. } => others += 1,
+            }
+        }
+        ensure!(
+            public.len() >= others,
+            "file is also encrypted for {others} other public keys, which all have to be given \
+             again as recipients, but {} are given",
+            public.len()
+        );
+
+        let mut rewrapped = vec![to.wrap(data_key, &mut rng)?];
+        for public in public {
+            rewrapped.push(public.wrap(data_key, &mut rng)?);
+        }
+        Ok(rewrapped)
+    }
+
+    fn unwrap(&self, wanted: Option<&str>, recipients: &[Recipient]) -> eyre::Result<ArmoredKey> {
+        for (name, key) in self.candidates(wanted)? {
+            if let Some(data_key) = key
+                .unwrap(recipients)
+                .wrap_err_with(|| format!("failed to unwrap data key with key {name:?}"))?
+            {
+                return Ok(data_key);
+            }
+        }
+
+        let available = recipients
+            .iter()
+            .map(|recipient| match recipient {
+                Recipient::Key { key_id, .. } => KeyId::from_slice(key_id)
+                    .map_or_else(|_| "malformed key id".to_owned(), |id| self.describe(id)),
+                Recipient::X25519 { .. } => "an X25519 public key".to_owned(),
+            })
+            .join(", ");
+        match wanted {
+            Some(wanted) => bail!("file is encrypted for {available}, but entry says {wanted:?}"),
+            None => bail!("file is encrypted for {available}, none of which is configured"),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use chacha20poly1305::{KeyInit as _, XChaCha20Poly1305 as Cipher};
+    use clap::ValueEnum as _;
+    use indexmap::IndexMap;
+
+    use super::{ArmoredKey, KeyFormat, Keyring};
+    use crate::{envelope::Body, x25519};
+
+    fn key() -> ArmoredKey {
+        ArmoredKey::new(Cipher::generate_key(rand::thread_rng()))
+    }
+
+    fn keyring(keys: [(&str, &ArmoredKey); 2]) -> Keyring {
+        Keyring::new(
+            keys.into_iter()
+                .map(|(name, key)| (name.to_owned(), ArmoredKey::new(**key)))
+                .collect::<IndexMap<_, _>>(),
+        )
+    }
+
+    #[test]
+    fn formats() {
+        for &format in KeyFormat::value_variants() {
+            let mut key =
+                ArmoredKey::new(Cipher::generate_key(rand::thread_rng())).with_format(format);
+            if format != KeyFormat::Raw {
+                key = key.with_header("label", "db-host");
+            }
+            let parsed = ArmoredKey::parse(&key.encode()).unwrap();
+            assert_eq!(*parsed, *key);
+            assert_eq!(parsed.format(), Some(format));
+            assert_eq!(parsed.header(), key.header());
+        }
+    }
+
+    #[test]
+    fn unwrap() {
+        let mut rng = rand::thread_rng();
+        let (a, b, c) = (key(), key(), key());
+        let data_key = Cipher::generate_key(&mut rng);
+        let recipients = [
+            a.wrap(&data_key, &mut rng).unwrap(),
+            x25519::public(&b).wrap(&data_key, &mut rng).unwrap(),
+        ];
+
+        let keys = keyring([("b", &b), ("c", &c)]);
+        assert_eq!(*keys.unwrap(None, &recipients).unwrap(), data_key);
+        assert_eq!(*keys.unwrap(Some("b"), &recipients).unwrap(), data_key);
+        let err = keys.unwrap(Some("c"), &recipients).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "file is encrypted for unknown key {}, an X25519 public key, but entry says \"c\"",
+                a.id()
+            )
+        );
+
+        let keys = keyring([("c", &c), ("a", &a)]);
+        assert_eq!(*keys.unwrap(None, &recipients).unwrap(), data_key);
+        let keys = keyring([("c", &c), ("d", &key())]);
+        let err = keys.unwrap(None, &recipients).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "file is encrypted for unknown key {}, an X25519 public key, none of which is \
+                 configured",
+                a.id()
+            )
+        );
+    }
+
+    #[test]
+    fn rewrap() {
+        let mut rng = rand::thread_rng();
+        let (a, b, c, to, other) = (key(), key(), key(), key(), key());
+        let old_key = Cipher::generate_key(&mut rng);
+        let new_key = Cipher::generate_key(&mut rng);
+        let keys = keyring([("a", &a), ("b", &b)]);
+        let public = x25519::public(&other);
+        let recipients = [
+            a.wrap(&old_key, &mut rng).unwrap(),
+            x25519::public(&b).wrap(&old_key, &mut rng).unwrap(),
+            to.wrap(&old_key, &mut rng).unwrap(),
+            public.wrap(&old_key, &mut rng).unwrap(),
+        ];
+
+        // entries of the keyring and of `to` are dropped, the public key gets the new data key
+        let rewrapped = keys
+            .rewrap(&recipients, &new_key, &to, &[public], &mut rng)
+            .unwrap();
+        assert_eq!(rewrapped.len(), 2);
+        assert_eq!(*to.unwrap(&rewrapped[..1]).unwrap().unwrap(), new_key);
+        assert_eq!(*other.unwrap(&rewrapped[1..]).unwrap().unwrap(), new_key);
+        for key in [&a, &b] {
+            assert!(key.unwrap(&rewrapped).unwrap().is_none());
+        }
+
+        let err = keys
+            .rewrap(&recipients, &new_key, &to, &[], &mut rng)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "file is also encrypted for 1 other public keys, which all have to be given again as \
+             recipients, but 0 are given"
+        );
+
+        let recipients = [c.wrap(&old_key, &mut rng).unwrap()];
+        let err = keys
+            .rewrap(&recipients, &new_key, &to, &[], &mut rng)
+            .unwrap_err();
+        assert!(
+            err.to_string().starts_with(&format!(
+                "file is also encrypted for unknown key {}, which can't be given",
+                c.id()
+            )),
+            "{err}"
+        );
+    }
+
+    // HKDF-SHA256 of the key with the salt, and "classified file key" followed by the context as
+    // info, computed separately. Changing this makes existing files unreadable.
+    #[test]
+    fn file_key() {
+        let key = ArmoredKey::new([1; 32].into());
+        let body = |salt: Option<u8>, context: Option<&str>| Body {
+            salt: salt.map(|byte| vec![byte; 32]),
+            context: context.map(str::to_owned),
+            ..Body::default()
+        };
+        let file_key = |body: &Body| *key.file_key(body).unwrap();
+
+        assert_eq!(
+            file_key(&body(Some(2), None)).as_slice(),
+            [
+                0x97, 0x7e, 0x06, 0x69, 0xc2, 0xe7, 0x69, 0x4e, 0x6d, 0x38, 0xe7, 0x43, 0x90, 0x21,
+                0xf9, 0x7f, 0xe6, 0x89, 0x5b, 0xc7, 0x77, 0x70, 0x91, 0xed, 0xcc, 0x63, 0xd4, 0x28,
+                0x6e, 0xb4, 0xbc, 0xbf,
+            ]
+        );
+        assert_eq!(
+            file_key(&body(Some(2), Some("db"))).as_slice(),
+            [
+                0x9b, 0x1c, 0xe7, 0x61, 0x89, 0xfe, 0x57, 0x49, 0xd3, 0x06, 0x37, 0x0c, 0x0c, 0xd1,
+                0xbb, 0x26, 0x40, 0xf3, 0x5f, 0xa7, 0x6f, 0x3b, 0xe7, 0xb6, 0xde, 0x51, 0xd6, 0x88,
+                0xae, 0x2e, 0x85, 0x46,
+            ]
+        );
+        assert_ne!(
+            file_key(&body(Some(3), Some("db"))),
+            file_key(&body(Some(2), Some("db")))
+        );
+        assert_ne!(
+            file_key(&body(Some(2), Some("web"))),
+            file_key(&body(Some(2), Some("db")))
+        );
+
+        // files without a salt use the key itself, and salts have a fixed length
+        assert_eq!(file_key(&body(None, Some("db"))), *key);
+        let short = Body {
+            salt: Some(vec![2; 16]),
+            ..Body::default()
+        };
+        assert!(key.file_key(&short).is_err());
+    }
+}
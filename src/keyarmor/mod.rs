@@ -1,60 +1,515 @@
-use bitvec::{array::BitArray, order::Msb0};
-use color_eyre::eyre::{self, ensure, eyre};
+use std::{fmt, slice};
+
+use bitvec::{array::BitArray, order::Msb0, slice::BitSlice, vec::BitVec};
+use clap::ValueEnum;
+use color_eyre::eyre::{self, bail, ensure, eyre};
 use crc_any::CRCu8;
-use zeroize::Zeroize as _;
+use itertools::Itertools as _;
+use sha2::{Digest as _, Sha256};
+use unicode_normalization::UnicodeNormalization as _;
+use zeroize::{Zeroize as _, Zeroizing};
+
+// `static`s generated by build.rs from `wordlists/*.txt`, e.g. `ENGLISH`
+// (words are NFKD-normalized, as in BIP39)
+#[allow(clippy::unreadable_literal, clippy::unicode_not_nfc)]
+mod wordlists {
+    include!(concat!(env!("OUT_DIR"), "/wordlists.rs"));
+}
+
+// Payload lengths that are armored as is, with a bit of checksum per 32 bits of payload: the
+// BIP39 ones (so 16 to 32 bytes are standard 12 to 24-word mnemonics) and 64 bytes
+const SIZES: [usize; 6] = [16, 20, 24, 28, 32, 64];
+// Payloads of other lengths start with their length as a big-endian u16, and have at least a byte
+// of checksum
+const PREFIX_LEN: usize = 2;
+const MIN_CHECKSUM_BITS: usize = 8;
+
+// What the bits after the payload (filling up the last word) are
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Checksum {
+    // CRC8 of the key, used by older versions and still the default; only for 32-byte keys
+    Crc8,
+    // first bits of SHA-256 of the payload, as in BIP39, so standard tools can check the words
+    Bip39,
+}
+
+impl Checksum {
+    // `None` if the checksum can't have this many bits
+    fn compute(self, bytes: &[u8], bits: usize) -> Option<u64> {
+        match self {
+            Self::Crc8 if bits == 8 => {
+                let mut crc = CRCu8::crc8();
+                crc.digest(bytes);
+                Some(crc.get_crc().into())
+            }
+            Self::Crc8 => None,
+            Self::Bip39 => {
+                let mut first = [0_u8; 8];
+                first.copy_from_slice(&Sha256::digest(bytes)[..8]);
+                Some(u64::from_be_bytes(first) >> (64 - bits))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Crc8 => write!(f, "legacy CRC8"),
+            Self::Bip39 => write!(f, "BIP39"),
+        }
+    }
+}
+
+// How a payload is laid out in the bits of the words
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Layout {
+    // one of `SIZES`
+    Fixed(usize),
+    Prefixed(usize),
+}
+
+impl Layout {
+    fn new(len: usize) -> Self {
+        if SIZES.contains(&len) {
+            Self::Fixed(len)
+        } else {
+            Self::Prefixed(len)
+        }
+    }
+
+    // Where the payload starts
+    fn offset(self) -> usize {
+        match self {
+            Self::Fixed(_) => 0,
+            Self::Prefixed(_) => PREFIX_LEN,
+        }
+    }
+
+    fn len(self) -> usize {
+        match self {
+            Self::Fixed(len) | Self::Prefixed(len) => len,
+        }
+    }
+
+    // Length prefix and payload, which is what the checksum is of
+    fn data_bits(self) -> usize {
+        (self.offset() + self.len()) * 8
+    }
+
+    fn word_count(self, wordlist: Wordlist) -> usize {
+        let min_checksum_bits = match self {
+            Self::Fixed(len) => len / 4,
+            Self::Prefixed(_) => MIN_CHECKSUM_BITS,
+        };
+        let count = (self.data_bits() + min_checksum_bits).div_ceil(wordlist.bits());
+        // one more word, so the number of words tells prefixed payloads apart
+        match self {
+            Self::Prefixed(_) if Self::fixed(count, wordlist).is_some() => count + 1,
+            _ => count,
+        }
+    }
+
+    fn checksum_bits(self, wordlist: Wordlist) -> usize {
+        self.word_count(wordlist) * wordlist.bits() - self.data_bits()
+    }
+
+    fn fixed(count: usize, wordlist: Wordlist) -> Option<Self> {
+        SIZES
+            .into_iter()
+            .map(Self::Fixed)
+            .find(|layout| layout.word_count(wordlist) == count)
+    }
+
+    // Layout of the bits of `count` words
+    fn parse(raw: &[u8], count: usize, wordlist: Wordlist) -> Option<Self> {
+        if let Some(layout) = Self::fixed(count, wordlist) {
+            return Some(layout);
+        }
+        let prefix = raw.get(..PREFIX_LEN)?;
+        let layout = Self::Prefixed(u16::from_be_bytes([prefix[0], prefix[1]]).into());
+        (layout.word_count(wordlist) == count).then_some(layout)
+    }
+}
+
+// Which words the bits are spelled with. Lists are tried in this order when reading words.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Wordlist {
+    #[default]
+    English,
+    ChineseSimplified,
+    ChineseTraditional,
+    Czech,
+    French,
+    Italian,
+    Japanese,
+    Korean,
+    Portuguese,
+    Spanish,
+    // PGP word list: a byte per word, alternating between two-syllable and three-syllable words,
+    // so words read over the phone are hard to mishear, drop or swap unnoticed
+    Pgp,
+}
+
+impl Wordlist {
+    // Words that can be at `position`
+    // (`is_multiple_of` would need Rust 1.87, newer than the pinned toolchain)
+    #[allow(unknown_lints, clippy::manual_is_multiple_of)]
+    fn words(self, position: usize) -> &'static phf::OrderedSet<&'static str> {
+        match self {
+            Self::English => &wordlists::ENGLISH,
+            Self::ChineseSimplified => &wordlists::CHINESE_SIMPLIFIED,
+            Self::ChineseTraditional => &wordlists::CHINESE_TRADITIONAL,
+            Self::Czech => &wordlists::CZECH,
+            Self::French => &wordlists::FRENCH,
+            Self::Italian => &wordlists::ITALIAN,
+            Self::Japanese => &wordlists::JAPANESE,
+            Self::Korean => &wordlists::KOREAN,
+            Self::Portuguese => &wordlists::PORTUGUESE,
+            Self::Spanish => &wordlists::SPANISH,
+            Self::Pgp if position % 2 == 0 => &wordlists::PGP_EVEN,
+            Self::Pgp => &wordlists::PGP_ODD,
+        }
+    }
+
+    fn bits(self) -> usize {
+        match self {
+            Self::Pgp => 8,
+            _ => 11,
+        }
+    }
+
+    // BIP39 uses the ideographic space for Japanese
+    fn separator(self) -> &'static str {
+        match self {
+            Self::Japanese => "\u{3000}",
+            _ => " ",
+        }
+    }
+
+    // Indices of the words, if all of them are in the list
+    fn indices(self, words: &[&str]) -> Option<Zeroizing<Vec<usize>>> {
+        let mut indices = Zeroizing::new(Vec::with_capacity(words.len()));
+        for (position, word) in words.iter().enumerate() {
+            indices.push(lookup(self.words(position), word)?);
+        }
+        Some(indices)
+    }
+}
+
+impl fmt::Display for Wordlist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no word list is skipped");
+        write!(f, "{}", value.get_name())
+    }
+}
+
+// The list that knows the most of the words (the first one if it's a tie), with the indices of
+// the words (0 for unknown ones) and the positions of the unknown ones
+fn closest(
+    words: &[&str],
+    wordlists: &[Wordlist],
+) -> (Wordlist, Zeroizing<Vec<usize>>, Vec<usize>) {
+    wordlists
+        .iter()
+        .map(|&wordlist| {
+            let mut indices = Zeroizing::new(vec![0; words.len()]);
+            let mut unknown = Vec::new();
+            for (position, (index, word)) in indices.iter_mut().zip(words).enumerate() {
+                match lookup(wordlist.words(position), word) {
+                    Some(found) => *index = found,
+                    None => unknown.push(position),
+                }
+            }
+            (wordlist, indices, unknown)
+        })
+        .min_by_key(|(_, _, unknown)| unknown.len())
+        .expect("there is a word list")
+}
+
+// Value of up to 64 bits
+fn load(bits: &BitSlice<u8, Msb0>) -> u64 {
+    let mut arr = BitArray::<[u8; 8], Msb0>::default();
+    arr[64 - bits.len()..].copy_from_bitslice(bits);
+    u64::from_be_bytes(arr.into_inner())
+}
+
+// Stores the lowest `bits.len()` bits of `value`
+fn store(bits: &mut BitSlice<u8, Msb0>, value: u64) {
+    let arr = BitArray::<[u8; 8], Msb0>::new(value.to_be_bytes());
+    let len = bits.len();
+    bits.copy_from_bitslice(&arr[64 - len..]);
+}
 
 pub struct Words {
-    inner: BitArray<[u8; 33], Msb0>,
+    inner: BitVec<u8, Msb0>,
+    layout: Layout,
+    checksum: Checksum,
+    wordlist: Wordlist,
+}
+
+impl fmt::Display for Words {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.words().format(self.wordlist.separator()))
+    }
 }
 
 impl Words {
-    pub fn new(array: [u8; 32]) -> Self {
-        let mut inner = [0_u8; 33];
-        inner[..32].copy_from_slice(&array);
-        let mut crc = CRCu8::crc8();
-        crc.digest(&array);
-        inner[32] = crc.get_crc();
-        Self {
-            inner: inner.into(),
+    // Payloads of any length up to `u16::MAX` bytes, but the CRC8 checksum is only for 32 bytes
+    pub fn new(bytes: &[u8], checksum: Checksum, wordlist: Wordlist) -> eyre::Result<Self> {
+        let layout = Layout::new(bytes.len());
+        ensure!(
+            checksum == Checksum::Bip39 || layout == Layout::Fixed(32),
+            "the {checksum} checksum is only for 32-byte keys"
+        );
+        let mut inner = BitVec::repeat(false, layout.word_count(wordlist) * wordlist.bits());
+        let raw = inner.as_raw_mut_slice();
+        if let Layout::Prefixed(len) = layout {
+            let len = u16::try_from(len)
+                .map_err(|_| eyre!("at most {} bytes can be armored as words", u16::MAX))?;
+            raw[..PREFIX_LEN].copy_from_slice(&len.to_be_bytes());
         }
+        raw[layout.offset()..][..bytes.len()].copy_from_slice(bytes);
+        let value = checksum
+            .compute(
+                &raw[..layout.offset() + bytes.len()],
+                layout.checksum_bits(wordlist),
+            )
+            .expect("the checksum has as many bits as it should");
+        store(&mut inner[layout.data_bits()..], value);
+        Ok(Self {
+            inner,
+            layout,
+            checksum,
+            wordlist,
+        })
+    }
+
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    pub fn wordlist(&self) -> Wordlist {
+        self.wordlist
     }
 
-    pub fn bytes(&self) -> [u8; 32] {
-        let mut res = [0_u8; 32];
-        res.copy_from_slice(&self.inner.into_inner()[..32]);
-        res
+    pub fn bytes(&self) -> &[u8] {
+        &self.inner.as_raw_slice()[self.layout.offset()..][..self.layout.len()]
     }
 
     pub fn words(&self) -> impl Iterator<Item = &'static str> + '_ {
-        self.inner.chunks(11).map(|chunk| {
-            let mut arr = BitArray::<[u8; 8], Msb0>::default();
-            arr[53..][..chunk.len()].copy_from_bitslice(chunk);
-            WORDS
-                .index(usize::from_be_bytes(arr.into_inner()))
-                .copied()
-                .expect("word is not in range")
+        self.inner
+            .chunks(self.wordlist.bits())
+            .enumerate()
+            .map(move |(position, chunk)| {
+                let index = usize::try_from(load(chunk)).expect("word index fits in usize");
+                self.wordlist
+                    .words(position)
+                    .index(index)
+                    .copied()
+                    .expect("word is not in range")
+            })
+    }
+
+    // Finds out the list the words are from (unless it's given), and accepts both checksums; if
+    // they happen to be equal, the words are the same either way
+    pub fn from_words(words: &[&str], wordlist: Option<Wordlist>) -> eyre::Result<Self> {
+        ensure!(!words.is_empty(), "no words given");
+        let wordlists = wordlist
+            .as_ref()
+            .map_or(Wordlist::value_variants(), slice::from_ref);
+        let mut known = false;
+        let mut found = Vec::new();
+        for &wordlist in wordlists {
+            let Some(indices) = wordlist.indices(words) else {
+                continue;
+            };
+            known = true;
+            found.extend(Self::from_indices(wordlist, &indices));
+        }
+        // lists share some words, so a few words can be valid in more than one
+        if let [first, rest @ ..] = found.as_slice() {
+            ensure!(
+                rest.iter().all(|other| other.bytes() == first.bytes()),
+                "the words are valid in more than one word list ({}), the list has to be given",
+                found.iter().map(Self::wordlist).format(", ")
+            );
+        }
+        if let Some(words) = found.into_iter().next() {
+            return Ok(words);
+        }
+        if known {
+            bail!(
+                "wrong checksum: it's neither a CRC8 nor a BIP39 checksum \
+                 (`classified repair-key` can look for a mistyped word)"
+            );
+        }
+        let (wordlist, _, unknown) = closest(words, wordlists);
+        let position = unknown[0];
+        Err(unknown_word(wordlist.words(position), words[position]))
+    }
+
+    // Words of a 32-byte key (or share)
+    pub fn from_key_words(words: &[&str]) -> eyre::Result<Self> {
+        ensure!(
+            matches!(words.len(), 24 | 33),
+            "wrong number of words: {} instead of 24 (or 33 with the PGP word list)",
+            words.len()
+        );
+        let words = Self::from_words(words, None)?;
+        ensure!(
+            words.layout == Layout::Fixed(32),
+            "words are not a key: they're {} bytes long instead of 32",
+            words.bytes().len()
+        );
+        Ok(words)
+    }
+
+    fn from_indices(wordlist: Wordlist, indices: &[usize]) -> Option<Self> {
+        let bits = wordlist.bits();
+        let mut inner = BitVec::repeat(false, indices.len() * bits);
+        for (position, &index) in indices.iter().enumerate() {
+            store(&mut inner[position * bits..][..bits], index as u64);
+        }
+
+        let words =
+            Layout::parse(inner.as_raw_slice(), indices.len(), wordlist).and_then(|layout| {
+                let data = &inner.as_raw_slice()[..layout.offset() + layout.len()];
+                let stored = load(&inner[layout.data_bits()..]);
+                [Checksum::Crc8, Checksum::Bip39]
+                    .into_iter()
+                    .filter(|&checksum| checksum == Checksum::Bip39 || layout == Layout::Fixed(32))
+                    .find(|checksum| {
+                        checksum.compute(data, layout.checksum_bits(wordlist)) == Some(stored)
+                    })
+                    .map(|checksum| (layout, checksum))
+            });
+        let Some((layout, checksum)) = words else {
+            inner.as_raw_mut_slice().zeroize();
+            return None;
+        };
+        Some(Self {
+            inner,
+            layout,
+            checksum,
+            wordlist,
         })
     }
+}
+
+// A single word that can be replaced to make the checksum of mistyped words valid
+pub struct Repair {
+    // 0-based
+    pub position: usize,
+    pub word: &'static str,
+    pub checksum: Checksum,
+    // edit distance from the word that was there, the smaller the likelier
+    pub distance: usize,
+}
+
+/// All single-word substitutions that make the checksum valid, likeliest first, from the list
+/// most of the words are in. If a word is unknown, only that one is replaced.
+pub fn repair(words: &[&str]) -> Vec<Repair> {
+    let (wordlist, mut indices, unknown) = closest(words, Wordlist::value_variants());
+    let positions = match unknown.as_slice() {
+        [] => (0..words.len()).collect(),
+        [position] => vec![*position],
+        _ => return Vec::new(),
+    };
+
+    let mut repairs = Vec::new();
+    for position in positions {
+        let original = indices[position];
+        let normalized = normalize(words[position]);
+        for (index, word) in wordlist.words(position).iter().enumerate() {
+            if index == original && unknown.is_empty() {
+                continue;
+            }
+            indices[position] = index;
+            if let Some(words) = Words::from_indices(wordlist, &indices) {
+                repairs.push(Repair {
+                    position,
+                    word,
+                    checksum: words.checksum(),
+                    distance: distance(&normalized, word),
+                });
+            }
+        }
+        indices[position] = original;
+    }
+    repairs.sort_by_key(|repair| (repair.distance, repair.position));
+    repairs
+}
 
-    pub fn from_words(words: &[&str; 24]) -> eyre::Result<Self> {
-        let mut inner: BitArray<[u8; 33], Msb0> = BitArray::default();
-        let n = inner.len();
+// Words are compared after NFKD normalization (as in BIP39) and lowercasing
+fn normalize(word: &str) -> String {
+    word.nfkd().collect::<String>().to_lowercase()
+}
 
-        for word in words {
-            inner.shift_left(11);
-            let idx = WORDS
-                .get_index(word)
-                .ok_or_else(|| eyre!("unknown word {word:?}"))?;
-            let idx_arr = BitArray::<[u8; 8], Msb0>::new(idx.to_be_bytes());
-            inner[n - 11..].copy_from_bitslice(&idx_arr[53..]);
+// Optimal string alignment distance: like Levenshtein, but swapping two adjacent letters is
+// a single edit too
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<_> = a.chars().collect();
+    let b: Vec<_> = b.chars().collect();
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
         }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
 
-        let mut crc = CRCu8::crc8();
-        let arr = inner.into_inner();
-        crc.digest(&arr[..32]);
-        ensure!(crc.get_crc() == arr[32], "wrong key CRC8");
+// Index of a word in the list, also accepting unique prefixes of at least 4 letters (BIP39 words
+// are unique in their first 4 letters)
+fn lookup(set: &phf::OrderedSet<&str>, word: &str) -> Option<usize> {
+    let normalized = normalize(word);
+    if let Some(index) = set.get_index(normalized.as_str()) {
+        return Some(index);
+    }
+    if normalized.chars().count() >= 4 {
+        let mut matching = set
+            .iter()
+            .enumerate()
+            .filter(|(_, candidate)| candidate.starts_with(&normalized));
+        if let (Some((index, _)), None) = (matching.next(), matching.next()) {
+            return Some(index);
+        }
+    }
+    None
+}
 
-        Ok(Self { inner })
+// Error for a word that isn't in the list, suggesting the closest ones
+fn unknown_word(set: &phf::OrderedSet<&str>, word: &str) -> eyre::Report {
+    let normalized = normalize(word);
+    let suggestions: Vec<_> = set
+        .iter()
+        .map(|candidate| (distance(&normalized, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .sorted()
+        .collect();
+    let closest = suggestions.first().map(|(distance, _)| *distance);
+    let suggestions: Vec<_> = suggestions
+        .into_iter()
+        .take_while(|(distance, _)| Some(*distance) == closest)
+        .map(|(_, candidate)| format!("{candidate:?}"))
+        .take(3)
+        .collect();
+    match suggestions.as_slice() {
+        [] => eyre!("unknown word {word:?}"),
+        [single] => eyre!("unknown word {word:?}, did you mean {single}?"),
+        [init @ .., last] => eyre!(
+            "unknown word {word:?}, did you mean {} or {last}?",
+            init.iter().format(", ")
+        ),
     }
 }
 
@@ -67,230 +522,127 @@ impl Drop for Words {
 #[cfg(test)]
 #[allow(clippy::unwrap_used)]
 mod tests {
-    use super::Words;
-    use proptest::{collection::vec, prop_assert_eq, proptest};
+    use clap::ValueEnum as _;
+    use itertools::Itertools as _;
+    use proptest::{
+        collection::vec, prop_assert, prop_assert_eq, prop_oneof, proptest, test_runner::Config,
+    };
+
+    use super::{lookup, repair, unknown_word, wordlists::ENGLISH, Checksum, Wordlist, Words};
 
     proptest! {
         #[test]
-        fn armor(input in vec(0..=u8::MAX, 32)) {
-            let input = <[u8; 32]>::try_from(input).unwrap();
-            let words: Vec<_> = Words::new(input).words().collect::<Vec<_>>();
-            prop_assert_eq!(input, Words::from_words(words.as_slice().try_into().unwrap()).unwrap().bytes());
+        fn armor(input in prop_oneof![vec(0..=u8::MAX, 32), vec(0..=u8::MAX, 0..100)]) {
+            for &wordlist in Wordlist::value_variants() {
+                for checksum in [Checksum::Crc8, Checksum::Bip39] {
+                    let Ok(words) = Words::new(&input, checksum, wordlist) else {
+                        prop_assert!(checksum == Checksum::Crc8 && input.len() != 32);
+                        continue;
+                    };
+                    let armored = words.to_string();
+                    let words: Vec<_> = armored.split_whitespace().collect();
+                    // the words of short payloads may be valid in other lists too
+                    let parsed = Words::from_words(&words, None)
+                        .or_else(|_| Words::from_words(&words, Some(wordlist)))
+                        .unwrap();
+                    prop_assert_eq!(&input, parsed.bytes());
+                    // the list may be another one with the same words, e.g. for Chinese
+                    prop_assert_eq!(parsed.to_string(), armored);
+                    prop_assert!(
+                        parsed.checksum() == checksum
+                            || Checksum::Crc8.compute(&input, 8) == Checksum::Bip39.compute(&input, 8)
+                    );
+                }
+            }
+        }
+    }
+
+    proptest! {
+        // every case tries the whole word list
+        #![proptest_config(Config::with_cases(32))]
+
+        #[test]
+        fn one_typo(input in vec(0..=u8::MAX, 32), position in 0..24_usize) {
+            let words = Words::new(&input, Checksum::Bip39, Wordlist::English).unwrap();
+            let words: Vec<_> = words.words().collect();
+            let mut mistyped = words.clone();
+            let typo = format!("{}x", words[position]);
+            mistyped[position] = &typo;
+            let repairs = repair(&mistyped);
+            prop_assert!(repairs
+                .iter()
+                .any(|repair| repair.position == position && repair.word == words[position]));
         }
     }
-}
 
-// BIP39 list of words
-static WORDS: phf::OrderedSet<&str> = phf::phf_ordered_set! {
-    "abandon", "ability", "able", "about", "above", "absent", "absorb", "abstract", "absurd",
-    "abuse", "access", "accident", "account", "accuse", "achieve", "acid", "acoustic", "acquire",
-    "across", "act", "action", "actor", "actress", "actual", "adapt", "add", "addict", "address",
-    "adjust", "admit", "adult", "advance", "advice", "aerobic", "affair", "afford", "afraid",
-    "again", "age", "agent", "agree", "ahead", "aim", "air", "airport", "aisle", "alarm", "album",
-    "alcohol", "alert", "alien", "all", "alley", "allow", "almost", "alone", "alpha", "already",
-    "also", "alter", "always", "amateur", "amazing", "among", "amount", "amused", "analyst",
-    "anchor", "ancient", "anger", "angle", "angry", "animal", "ankle", "announce", "annual",
-    "another", "answer", "antenna", "antique", "anxiety", "any", "apart", "apology", "appear",
-    "apple", "approve", "april", "arch", "arctic", "area", "arena", "argue", "arm", "armed",
-    "armor", "army", "around", "arrange", "arrest", "arrive", "arrow", "art", "artefact", "artist",
-    "artwork", "ask", "aspect", "assault", "asset", "assist", "assume", "asthma", "athlete",
-    "atom", "attack", "attend", "attitude", "attract", "auction", "audit", "august", "aunt",
-    "author", "auto", "autumn", "average", "avocado", "avoid", "awake", "aware", "away", "awesome",
-    "awful", "awkward", "axis", "baby", "bachelor", "bacon", "badge", "bag", "balance", "balcony",
-    "ball", "bamboo", "banana", "banner", "bar", "barely", "bargain", "barrel", "base", "basic",
-    "basket", "battle", "beach", "bean", "beauty", "because", "become", "beef", "before", "begin",
-    "behave", "behind", "believe", "below", "belt", "bench", "benefit", "best", "betray", "better",
-    "between", "beyond", "bicycle", "bid", "bike", "bind", "biology", "bird", "birth", "bitter",
-    "black", "blade", "blame", "blanket", "blast", "bleak", "bless", "blind", "blood", "blossom",
-    "blouse", "blue", "blur", "blush", "board", "boat", "body", "boil", "bomb", "bone", "bonus",
-    "book", "boost", "border", "boring", "borrow", "boss", "bottom", "bounce", "box", "boy",
-    "bracket", "brain", "brand", "brass", "brave", "bread", "breeze", "brick", "bridge", "brief",
-    "bright", "bring", "brisk", "broccoli", "broken", "bronze", "broom", "brother", "brown",
-    "brush", "bubble", "buddy", "budget", "buffalo", "build", "bulb", "bulk", "bullet", "bundle",
-    "bunker", "burden", "burger", "burst", "bus", "business", "busy", "butter", "buyer", "buzz",
-    "cabbage", "cabin", "cable", "cactus", "cage", "cake", "call", "calm", "camera", "camp", "can",
-    "canal", "cancel", "candy", "cannon", "canoe", "canvas", "canyon", "capable", "capital",
-    "captain", "car", "carbon", "card", "cargo", "carpet", "carry", "cart", "case", "cash",
-    "casino", "castle", "casual", "cat", "catalog", "catch", "category", "cattle", "caught",
-    "cause", "caution", "cave", "ceiling", "celery", "cement", "census", "century", "cereal",
-    "certain", "chair", "chalk", "champion", "change", "chaos", "chapter", "charge", "chase",
-    "chat", "cheap", "check", "cheese", "chef", "cherry", "chest", "chicken", "chief", "child",
-    "chimney", "choice", "choose", "chronic", "chuckle", "chunk", "churn", "cigar", "cinnamon",
-    "circle", "citizen", "city", "civil", "claim", "clap", "clarify", "claw", "clay", "clean",
-    "clerk", "clever", "click", "client", "cliff", "climb", "clinic", "clip", "clock", "clog",
-    "close", "cloth", "cloud", "clown", "club", "clump", "cluster", "clutch", "coach", "coast",
-    "coconut", "code", "coffee", "coil", "coin", "collect", "color", "column", "combine", "come",
-    "comfort", "comic", "common", "company", "concert", "conduct", "confirm", "congress",
-    "connect", "consider", "control", "convince", "cook", "cool", "copper", "copy", "coral",
-    "core", "corn", "correct", "cost", "cotton", "couch", "country", "couple", "course", "cousin",
-    "cover", "coyote", "crack", "cradle", "craft", "cram", "crane", "crash", "crater", "crawl",
-    "crazy", "cream", "credit", "creek", "crew", "cricket", "crime", "crisp", "critic", "crop",
-    "cross", "crouch", "crowd", "crucial", "cruel", "cruise", "crumble", "crunch", "crush", "cry",
-    "crystal", "cube", "culture", "cup", "cupboard", "curious", "current", "curtain", "curve",
-    "cushion", "custom", "cute", "cycle", "dad", "damage", "damp", "dance", "danger", "daring",
-    "dash", "daughter", "dawn", "day", "deal", "debate", "debris", "decade", "december", "decide",
-    "decline", "decorate", "decrease", "deer", "defense", "define", "defy", "degree", "delay",
-    "deliver", "demand", "demise", "denial", "dentist", "deny", "depart", "depend", "deposit",
-    "depth", "deputy", "derive", "describe", "desert", "design", "desk", "despair", "destroy",
-    "detail", "detect", "develop", "device", "devote", "diagram", "dial", "diamond", "diary",
-    "dice", "diesel", "diet", "differ", "digital", "dignity", "dilemma", "dinner", "dinosaur",
-    "direct", "dirt", "disagree", "discover", "disease", "dish", "dismiss", "disorder", "display",
-    "distance", "divert", "divide", "divorce", "dizzy", "doctor", "document", "dog", "doll",
-    "dolphin", "domain", "donate", "donkey", "donor", "door", "dose", "double", "dove", "draft",
-    "dragon", "drama", "drastic", "draw", "dream", "dress", "drift", "drill", "drink", "drip",
-    "drive", "drop", "drum", "dry", "duck", "dumb", "dune", "during", "dust", "dutch", "duty",
-    "dwarf", "dynamic", "eager", "eagle", "early", "earn", "earth", "easily", "east", "easy",
-    "echo", "ecology", "economy", "edge", "edit", "educate", "effort", "egg", "eight", "either",
-    "elbow", "elder", "electric", "elegant", "element", "elephant", "elevator", "elite", "else",
-    "embark", "embody", "embrace", "emerge", "emotion", "employ", "empower", "empty", "enable",
-    "enact", "end", "endless", "endorse", "enemy", "energy", "enforce", "engage", "engine",
-    "enhance", "enjoy", "enlist", "enough", "enrich", "enroll", "ensure", "enter", "entire",
-    "entry", "envelope", "episode", "equal", "equip", "era", "erase", "erode", "erosion", "error",
-    "erupt", "escape", "essay", "essence", "estate", "eternal", "ethics", "evidence", "evil",
-    "evoke", "evolve", "exact", "example", "excess", "exchange", "excite", "exclude", "excuse",
-    "execute", "exercise", "exhaust", "exhibit", "exile", "exist", "exit", "exotic", "expand",
-    "expect", "expire", "explain", "expose", "express", "extend", "extra", "eye", "eyebrow",
-    "fabric", "face", "faculty", "fade", "faint", "faith", "fall", "false", "fame", "family",
-    "famous", "fan", "fancy", "fantasy", "farm", "fashion", "fat", "fatal", "father", "fatigue",
-    "fault", "favorite", "feature", "february", "federal", "fee", "feed", "feel", "female",
-    "fence", "festival", "fetch", "fever", "few", "fiber", "fiction", "field", "figure", "file",
-    "film", "filter", "final", "find", "fine", "finger", "finish", "fire", "firm", "first",
-    "fiscal", "fish", "fit", "fitness", "fix", "flag", "flame", "flash", "flat", "flavor", "flee",
-    "flight", "flip", "float", "flock", "floor", "flower", "fluid", "flush", "fly", "foam",
-    "focus", "fog", "foil", "fold", "follow", "food", "foot", "force", "forest", "forget", "fork",
-    "fortune", "forum", "forward", "fossil", "foster", "found", "fox", "fragile", "frame",
-    "frequent", "fresh", "friend", "fringe", "frog", "front", "frost", "frown", "frozen", "fruit",
-    "fuel", "fun", "funny", "furnace", "fury", "future", "gadget", "gain", "galaxy", "gallery",
-    "game", "gap", "garage", "garbage", "garden", "garlic", "garment", "gas", "gasp", "gate",
-    "gather", "gauge", "gaze", "general", "genius", "genre", "gentle", "genuine", "gesture",
-    "ghost", "giant", "gift", "giggle", "ginger", "giraffe", "girl", "give", "glad", "glance",
-    "glare", "glass", "glide", "glimpse", "globe", "gloom", "glory", "glove", "glow", "glue",
-    "goat", "goddess", "gold", "good", "goose", "gorilla", "gospel", "gossip", "govern", "gown",
-    "grab", "grace", "grain", "grant", "grape", "grass", "gravity", "great", "green", "grid",
-    "grief", "grit", "grocery", "group", "grow", "grunt", "guard", "guess", "guide", "guilt",
-    "guitar", "gun", "gym", "habit", "hair", "half", "hammer", "hamster", "hand", "happy",
-    "harbor", "hard", "harsh", "harvest", "hat", "have", "hawk", "hazard", "head", "health",
-    "heart", "heavy", "hedgehog", "height", "hello", "helmet", "help", "hen", "hero", "hidden",
-    "high", "hill", "hint", "hip", "hire", "history", "hobby", "hockey", "hold", "hole", "holiday",
-    "hollow", "home", "honey", "hood", "hope", "horn", "horror", "horse", "hospital", "host",
-    "hotel", "hour", "hover", "hub", "huge", "human", "humble", "humor", "hundred", "hungry",
-    "hunt", "hurdle", "hurry", "hurt", "husband", "hybrid", "ice", "icon", "idea", "identify",
-    "idle", "ignore", "ill", "illegal", "illness", "image", "imitate", "immense", "immune",
-    "impact", "impose", "improve", "impulse", "inch", "include", "income", "increase", "index",
-    "indicate", "indoor", "industry", "infant", "inflict", "inform", "inhale", "inherit",
-    "initial", "inject", "injury", "inmate", "inner", "innocent", "input", "inquiry", "insane",
-    "insect", "inside", "inspire", "install", "intact", "interest", "into", "invest", "invite",
-    "involve", "iron", "island", "isolate", "issue", "item", "ivory", "jacket", "jaguar", "jar",
-    "jazz", "jealous", "jeans", "jelly", "jewel", "job", "join", "joke", "journey", "joy", "judge",
-    "juice", "jump", "jungle", "junior", "junk", "just", "kangaroo", "keen", "keep", "ketchup",
-    "key", "kick", "kid", "kidney", "kind", "kingdom", "kiss", "kit", "kitchen", "kite", "kitten",
-    "kiwi", "knee", "knife", "knock", "know", "lab", "label", "labor", "ladder", "lady", "lake",
-    "lamp", "language", "laptop", "large", "later", "latin", "laugh", "laundry", "lava", "law",
-    "lawn", "lawsuit", "layer", "lazy", "leader", "leaf", "learn", "leave", "lecture", "left",
-    "leg", "legal", "legend", "leisure", "lemon", "lend", "length", "lens", "leopard", "lesson",
-    "letter", "level", "liar", "liberty", "library", "license", "life", "lift", "light", "like",
-    "limb", "limit", "link", "lion", "liquid", "list", "little", "live", "lizard", "load", "loan",
-    "lobster", "local", "lock", "logic", "lonely", "long", "loop", "lottery", "loud", "lounge",
-    "love", "loyal", "lucky", "luggage", "lumber", "lunar", "lunch", "luxury", "lyrics", "machine",
-    "mad", "magic", "magnet", "maid", "mail", "main", "major", "make", "mammal", "man", "manage",
-    "mandate", "mango", "mansion", "manual", "maple", "marble", "march", "margin", "marine",
-    "market", "marriage", "mask", "mass", "master", "match", "material", "math", "matrix",
-    "matter", "maximum", "maze", "meadow", "mean", "measure", "meat", "mechanic", "medal", "media",
-    "melody", "melt", "member", "memory", "mention", "menu", "mercy", "merge", "merit", "merry",
-    "mesh", "message", "metal", "method", "middle", "midnight", "milk", "million", "mimic", "mind",
-    "minimum", "minor", "minute", "miracle", "mirror", "misery", "miss", "mistake", "mix", "mixed",
-    "mixture", "mobile", "model", "modify", "mom", "moment", "monitor", "monkey", "monster",
-    "month", "moon", "moral", "more", "morning", "mosquito", "mother", "motion", "motor",
-    "mountain", "mouse", "move", "movie", "much", "muffin", "mule", "multiply", "muscle", "museum",
-    "mushroom", "music", "must", "mutual", "myself", "mystery", "myth", "naive", "name", "napkin",
-    "narrow", "nasty", "nation", "nature", "near", "neck", "need", "negative", "neglect",
-    "neither", "nephew", "nerve", "nest", "net", "network", "neutral", "never", "news", "next",
-    "nice", "night", "noble", "noise", "nominee", "noodle", "normal", "north", "nose", "notable",
-    "note", "nothing", "notice", "novel", "now", "nuclear", "number", "nurse", "nut", "oak",
-    "obey", "object", "oblige", "obscure", "observe", "obtain", "obvious", "occur", "ocean",
-    "october", "odor", "off", "offer", "office", "often", "oil", "okay", "old", "olive", "olympic",
-    "omit", "once", "one", "onion", "online", "only", "open", "opera", "opinion", "oppose",
-    "option", "orange", "orbit", "orchard", "order", "ordinary", "organ", "orient", "original",
-    "orphan", "ostrich", "other", "outdoor", "outer", "output", "outside", "oval", "oven", "over",
-    "own", "owner", "oxygen", "oyster", "ozone", "pact", "paddle", "page", "pair", "palace",
-    "palm", "panda", "panel", "panic", "panther", "paper", "parade", "parent", "park", "parrot",
-    "party", "pass", "patch", "path", "patient", "patrol", "pattern", "pause", "pave", "payment",
-    "peace", "peanut", "pear", "peasant", "pelican", "pen", "penalty", "pencil", "people",
-    "pepper", "perfect", "permit", "person", "pet", "phone", "photo", "phrase", "physical",
-    "piano", "picnic", "picture", "piece", "pig", "pigeon", "pill", "pilot", "pink", "pioneer",
-    "pipe", "pistol", "pitch", "pizza", "place", "planet", "plastic", "plate", "play", "please",
-    "pledge", "pluck", "plug", "plunge", "poem", "poet", "point", "polar", "pole", "police",
-    "pond", "pony", "pool", "popular", "portion", "position", "possible", "post", "potato",
-    "pottery", "poverty", "powder", "power", "practice", "praise", "predict", "prefer", "prepare",
-    "present", "pretty", "prevent", "price", "pride", "primary", "print", "priority", "prison",
-    "private", "prize", "problem", "process", "produce", "profit", "program", "project", "promote",
-    "proof", "property", "prosper", "protect", "proud", "provide", "public", "pudding", "pull",
-    "pulp", "pulse", "pumpkin", "punch", "pupil", "puppy", "purchase", "purity", "purpose",
-    "purse", "push", "put", "puzzle", "pyramid", "quality", "quantum", "quarter", "question",
-    "quick", "quit", "quiz", "quote", "rabbit", "raccoon", "race", "rack", "radar", "radio",
-    "rail", "rain", "raise", "rally", "ramp", "ranch", "random", "range", "rapid", "rare", "rate",
-    "rather", "raven", "raw", "razor", "ready", "real", "reason", "rebel", "rebuild", "recall",
-    "receive", "recipe", "record", "recycle", "reduce", "reflect", "reform", "refuse", "region",
-    "regret", "regular", "reject", "relax", "release", "relief", "rely", "remain", "remember",
-    "remind", "remove", "render", "renew", "rent", "reopen", "repair", "repeat", "replace",
-    "report", "require", "rescue", "resemble", "resist", "resource", "response", "result",
-    "retire", "retreat", "return", "reunion", "reveal", "review", "reward", "rhythm", "rib",
-    "ribbon", "rice", "rich", "ride", "ridge", "rifle", "right", "rigid", "ring", "riot", "ripple",
-    "risk", "ritual", "rival", "river", "road", "roast", "robot", "robust", "rocket", "romance",
-    "roof", "rookie", "room", "rose", "rotate", "rough", "round", "route", "royal", "rubber",
-    "rude", "rug", "rule", "run", "runway", "rural", "sad", "saddle", "sadness", "safe", "sail",
-    "salad", "salmon", "salon", "salt", "salute", "same", "sample", "sand", "satisfy", "satoshi",
-    "sauce", "sausage", "save", "say", "scale", "scan", "scare", "scatter", "scene", "scheme",
-    "school", "science", "scissors", "scorpion", "scout", "scrap", "screen", "script", "scrub",
-    "sea", "search", "season", "seat", "second", "secret", "section", "security", "seed", "seek",
-    "segment", "select", "sell", "seminar", "senior", "sense", "sentence", "series", "service",
-    "session", "settle", "setup", "seven", "shadow", "shaft", "shallow", "share", "shed", "shell",
-    "sheriff", "shield", "shift", "shine", "ship", "shiver", "shock", "shoe", "shoot", "shop",
-    "short", "shoulder", "shove", "shrimp", "shrug", "shuffle", "shy", "sibling", "sick", "side",
-    "siege", "sight", "sign", "silent", "silk", "silly", "silver", "similar", "simple", "since",
-    "sing", "siren", "sister", "situate", "six", "size", "skate", "sketch", "ski", "skill", "skin",
-    "skirt", "skull", "slab", "slam", "sleep", "slender", "slice", "slide", "slight", "slim",
-    "slogan", "slot", "slow", "slush", "small", "smart", "smile", "smoke", "smooth", "snack",
-    "snake", "snap", "sniff", "snow", "soap", "soccer", "social", "sock", "soda", "soft", "solar",
-    "soldier", "solid", "solution", "solve", "someone", "song", "soon", "sorry", "sort", "soul",
-    "sound", "soup", "source", "south", "space", "spare", "spatial", "spawn", "speak", "special",
-    "speed", "spell", "spend", "sphere", "spice", "spider", "spike", "spin", "spirit", "split",
-    "spoil", "sponsor", "spoon", "sport", "spot", "spray", "spread", "spring", "spy", "square",
-    "squeeze", "squirrel", "stable", "stadium", "staff", "stage", "stairs", "stamp", "stand",
-    "start", "state", "stay", "steak", "steel", "stem", "step", "stereo", "stick", "still",
-    "sting", "stock", "stomach", "stone", "stool", "story", "stove", "strategy", "street",
-    "strike", "strong", "struggle", "student", "stuff", "stumble", "style", "subject", "submit",
-    "subway", "success", "such", "sudden", "suffer", "sugar", "suggest", "suit", "summer", "sun",
-    "sunny", "sunset", "super", "supply", "supreme", "sure", "surface", "surge", "surprise",
-    "surround", "survey", "suspect", "sustain", "swallow", "swamp", "swap", "swarm", "swear",
-    "sweet", "swift", "swim", "swing", "switch", "sword", "symbol", "symptom", "syrup", "system",
-    "table", "tackle", "tag", "tail", "talent", "talk", "tank", "tape", "target", "task", "taste",
-    "tattoo", "taxi", "teach", "team", "tell", "ten", "tenant", "tennis", "tent", "term", "test",
-    "text", "thank", "that", "theme", "then", "theory", "there", "they", "thing", "this",
-    "thought", "three", "thrive", "throw", "thumb", "thunder", "ticket", "tide", "tiger", "tilt",
-    "timber", "time", "tiny", "tip", "tired", "tissue", "title", "toast", "tobacco", "today",
-    "toddler", "toe", "together", "toilet", "token", "tomato", "tomorrow", "tone", "tongue",
-    "tonight", "tool", "tooth", "top", "topic", "topple", "torch", "tornado", "tortoise", "toss",
-    "total", "tourist", "toward", "tower", "town", "toy", "track", "trade", "traffic", "tragic",
-    "train", "transfer", "trap", "trash", "travel", "tray", "treat", "tree", "trend", "trial",
-    "tribe", "trick", "trigger", "trim", "trip", "trophy", "trouble", "truck", "true", "truly",
-    "trumpet", "trust", "truth", "try", "tube", "tuition", "tumble", "tuna", "tunnel", "turkey",
-    "turn", "turtle", "twelve", "twenty", "twice", "twin", "twist", "two", "type", "typical",
-    "ugly", "umbrella", "unable", "unaware", "uncle", "uncover", "under", "undo", "unfair",
-    "unfold", "unhappy", "uniform", "unique", "unit", "universe", "unknown", "unlock", "until",
-    "unusual", "unveil", "update", "upgrade", "uphold", "upon", "upper", "upset", "urban", "urge",
-    "usage", "use", "used", "useful", "useless", "usual", "utility", "vacant", "vacuum", "vague",
-    "valid", "valley", "valve", "van", "vanish", "vapor", "various", "vast", "vault", "vehicle",
-    "velvet", "vendor", "venture", "venue", "verb", "verify", "version", "very", "vessel",
-    "veteran", "viable", "vibrant", "vicious", "victory", "video", "view", "village", "vintage",
-    "violin", "virtual", "virus", "visa", "visit", "visual", "vital", "vivid", "vocal", "voice",
-    "void", "volcano", "volume", "vote", "voyage", "wage", "wagon", "wait", "walk", "wall",
-    "walnut", "want", "warfare", "warm", "warrior", "wash", "wasp", "waste", "water", "wave",
-    "way", "wealth", "weapon", "wear", "weasel", "weather", "web", "wedding", "weekend", "weird",
-    "welcome", "west", "wet", "whale", "what", "wheat", "wheel", "when", "where", "whip",
-    "whisper", "wide", "width", "wife", "wild", "will", "win", "window", "wine", "wing", "wink",
-    "winner", "winter", "wire", "wisdom", "wise", "wish", "witness", "wolf", "woman", "wonder",
-    "wood", "wool", "word", "work", "world", "worry", "worth", "wrap", "wreck", "wrestle", "wrist",
-    "write", "wrong", "yard", "year", "yellow", "you", "young", "youth", "zebra", "zero", "zone",
-    "zoo",
-};
+    #[test]
+    fn lookup_typos() {
+        assert_eq!(lookup(&ENGLISH, "abandon"), Some(0));
+        assert_eq!(lookup(&ENGLISH, "ABANDON"), Some(0));
+        assert_eq!(lookup(&ENGLISH, "aban"), Some(0));
+        assert_eq!(lookup(&ENGLISH, "zoo"), Some(2047));
+        assert_eq!(lookup(&ENGLISH, "abanon"), None);
+        let err = unknown_word(&ENGLISH, "abanon").to_string();
+        assert!(err.contains("did you mean \"abandon\"?"), "{err}");
+    }
+
+    // test vectors from the BIP39 spec
+    #[test]
+    fn bip39() {
+        for (entropy, mnemonic) in [
+            (
+                &[0x00; 16][..],
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+                 abandon about",
+            ),
+            (
+                &[0x7f; 16],
+                "legal winner thank year wave sausage worth useful legal winner thank yellow",
+            ),
+            (
+                &[0x00; 24],
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+                 abandon abandon abandon abandon abandon abandon abandon agent",
+            ),
+            (
+                &[0x00; 32],
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+                 abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+                 abandon abandon abandon art",
+            ),
+            (
+                &[0x7f; 32],
+                "legal winner thank year wave sausage worth useful legal winner thank year wave \
+                 sausage worth useful legal winner thank year wave sausage worth title",
+            ),
+            (
+                &[0xff; 32],
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo \
+                 zoo zoo zoo vote",
+            ),
+        ] {
+            let words = Words::new(entropy, Checksum::Bip39, Wordlist::English)
+                .unwrap()
+                .to_string();
+            assert_eq!(words, mnemonic);
+        }
+    }
+
+    // example from the PGP word list article on Wikipedia
+    #[test]
+    fn pgp() {
+        let mut bytes = [0_u8; 32];
+        bytes[..20].copy_from_slice(&[
+            0xe5, 0x82, 0x94, 0xf2, 0xe9, 0xa2, 0x27, 0x48, 0x6e, 0x8b, 0x06, 0x1b, 0x31, 0xcc,
+            0x52, 0x8f, 0xd7, 0xfa, 0x3f, 0x19,
+        ]);
+        let words = Words::new(&bytes, Checksum::Crc8, Wordlist::Pgp).unwrap();
+        let words = words.words().take(20).join(" ");
+        assert_eq!(
+            words,
+            "topmost istanbul pluto vagabond treadmill pacific brackish dictator goldfish medusa \
+             afflict bravado chatter revolver dupont midsummer stopwatch whimsical cowbell \
+             bottomless"
+        );
+    }
+}
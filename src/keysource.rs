@@ -0,0 +1,279 @@
+// Where key files are read from. Besides paths, keys can come from an inherited file descriptor,
+// an environment variable, a systemd credential (`LoadCredential=`) or the output of a command
+// (e.g. a password manager), so they don't have to be stored at a fixed path that everyone can see.
+
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs,
+    os::unix::{
+        ffi::OsStringExt as _,
+        io::{AsRawFd as _, RawFd},
+    },
+    path::{Path, PathBuf},
+    process::{self, Child, ExitStatus, Stdio},
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{self, bail, ensure, eyre, WrapErr as _};
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags},
+};
+use serde::Deserialize;
+use zeroize::Zeroizing;
+
+const FD_PREFIX: &str = "fd:";
+const ENV_PREFIX: &str = "env:";
+const CREDENTIAL_PREFIX: &str = "credential:";
+const COMMAND_PREFIX: &str = "command:";
+// key files are tiny, this is only so a wrong file descriptor or command can't fill up the memory
+const MAX_KEY_FILE_LEN: usize = 64 * 1024;
+// long enough to unlock a password manager or touch a security key
+// (`Duration::from_mins` would need Rust 1.91, newer than the pinned toolchain)
+#[allow(unknown_lints, clippy::duration_suboptimal_units)]
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+// a file descriptor can be read only once, so its contents are kept for every key that uses it
+static FD_CONTENTS: Mutex<BTreeMap<RawFd, Zeroizing<Vec<u8>>>> = Mutex::new(BTreeMap::new());
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum KeySource {
+    File(PathBuf),
+    Fd(RawFd),
+    Env(String),
+    Credential(String),
+    // program and arguments, never empty
+    Command(Vec<String>),
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Fd(fd) => write!(f, "{FD_PREFIX}{fd}"),
+            Self::Env(name) => write!(f, "{ENV_PREFIX}{name}"),
+            Self::Credential(name) => write!(f, "{CREDENTIAL_PREFIX}{name}"),
+            Self::Command(args) => write!(f, "{COMMAND_PREFIX}{}", args.join(" ")),
+        }
+    }
+}
+
+impl FromStr for KeySource {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        if let Some(fd) = s.strip_prefix(FD_PREFIX) {
+            let fd = fd
+                .parse()
+                .map_err(|_| eyre!("{s:?} should be `fd:` followed by a file descriptor number"))?;
+            Ok(Self::Fd(fd))
+        } else if let Some(name) = s.strip_prefix(ENV_PREFIX) {
+            ensure!(
+                !name.is_empty() && !name.contains('='),
+                "{s:?} should be `env:` followed by the name of an environment variable"
+            );
+            Ok(Self::Env(name.to_owned()))
+        } else if let Some(name) = s.strip_prefix(CREDENTIAL_PREFIX) {
+            // same rules as systemd's
+            ensure!(
+                !name.is_empty() && name != "." && name != ".." && !name.contains('/'),
+                "{s:?} should be `credential:` followed by the name of a systemd credential"
+            );
+            Ok(Self::Credential(name.to_owned()))
+        } else if let Some(command) = s.strip_prefix(COMMAND_PREFIX) {
+            // no quoting, arguments are separated by whitespace
+            let args: Vec<String> = command.split_whitespace().map(str::to_owned).collect();
+            ensure!(
+                !args.is_empty(),
+                "{s:?} should be `command:` followed by a program and its arguments"
+            );
+            Ok(Self::Command(args))
+        } else {
+            ensure!(!s.is_empty(), "path to the key file is empty");
+            Ok(Self::File(PathBuf::from(s)))
+        }
+    }
+}
+
+impl TryFrom<String> for KeySource {
+    type Error = eyre::Report;
+
+    fn try_from(s: String) -> eyre::Result<Self> {
+        s.parse()
+    }
+}
+
+impl KeySource {
+    pub fn read(&self) -> eyre::Result<Zeroizing<Vec<u8>>> {
+        match self {
+            Self::File(path) => Ok(Zeroizing::new(
+                fs::read(path).wrap_err("failed to read key file")?,
+            )),
+            Self::Fd(fd) => {
+                let mut contents = FD_CONTENTS.lock().unwrap_or_else(PoisonError::into_inner);
+                if !contents.contains_key(fd) {
+                    contents.insert(*fd, read_fd(*fd, None)?);
+                }
+                Ok(contents[fd].clone())
+            }
+            Self::Env(name) => {
+                let value = env::var_os(name)
+                    .ok_or_else(|| eyre!("environment variable {name} is not set"))?;
+                Ok(Zeroizing::new(value.into_vec()))
+            }
+            Self::Credential(name) => {
+                let dir = env::var_os("CREDENTIALS_DIRECTORY").ok_or_else(|| {
+                    eyre!(
+                        "$CREDENTIALS_DIRECTORY is not set: credentials only work in systemd \
+                         services that load them with LoadCredential= or SetCredential="
+                    )
+                })?;
+                Ok(Zeroizing::new(
+                    fs::read(Path::new(&dir).join(name))
+                        .wrap_err_with(|| format!("failed to read credential {name}"))?,
+                ))
+            }
+            Self::Command(args) => run(args),
+        }
+    }
+}
+
+// Reads the key from the stdout of a command, which gets no stdin (that may be the file to
+// decrypt) and shares stderr, so it can explain why it failed
+fn run(args: &[String]) -> eyre::Result<Zeroizing<Vec<u8>>> {
+    let (program, args) = args.split_first().expect("key commands aren't empty");
+    let mut child = process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .wrap_err("failed to run key command")?;
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let res = read_fd(stdout.as_raw_fd(), Some(deadline)).and_then(|key| {
+        let status = wait(&mut child, deadline)?;
+        ensure!(status.success(), "key command failed with {status}");
+        Ok(key)
+    });
+    if res.is_err() {
+        drop(child.kill());
+        drop(child.wait());
+    }
+    res
+}
+
+fn wait(child: &mut Child, deadline: Instant) -> eyre::Result<ExitStatus> {
+    loop {
+        if let Some(status) = child
+            .try_wait()
+            .wrap_err("failed to wait for key command")?
+        {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            bail!("key command timed out");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Reads until the end, without taking ownership of the descriptor, failing if that's not done by
+// `deadline`
+fn read_fd(fd: RawFd, deadline: Option<Instant>) -> eyre::Result<Zeroizing<Vec<u8>>> {
+    let mut buf = Zeroizing::new(vec![0_u8; MAX_KEY_FILE_LEN + 1]);
+    let mut len = 0;
+    while len < buf.len() {
+        if let Some(deadline) = deadline {
+            let timeout = deadline
+                .saturating_duration_since(Instant::now())
+                .as_millis();
+            match poll(
+                &mut [PollFd::new(fd, PollFlags::POLLIN)],
+                i32::try_from(timeout).unwrap_or(i32::MAX),
+            ) {
+                Ok(0) => bail!("key command timed out"),
+                Ok(_) => {}
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err).wrap_err("failed to read key"),
+            }
+        }
+        match nix::unistd::read(fd, &mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(Errno::EINTR) => {}
+            Err(err) => return Err(err).wrap_err("failed to read key"),
+        }
+    }
+    ensure!(len <= MAX_KEY_FILE_LEN, "key is longer than 64 KiB");
+    Ok(Zeroizing::new(buf[..len].to_vec()))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::path::PathBuf;
+
+    use nix::unistd::{close, pipe, write};
+
+    use super::KeySource;
+
+    #[test]
+    fn parse() {
+        for (spec, source) in [
+            ("/etc/key", KeySource::File(PathBuf::from("/etc/key"))),
+            ("fd:3", KeySource::Fd(3)),
+            (
+                "env:CLASSIFIED_KEY",
+                KeySource::Env("CLASSIFIED_KEY".to_owned()),
+            ),
+            ("credential:key", KeySource::Credential("key".to_owned())),
+            (
+                "command:pass show classified",
+                KeySource::Command(vec![
+                    "pass".to_owned(),
+                    "show".to_owned(),
+                    "classified".to_owned(),
+                ]),
+            ),
+        ] {
+            let parsed: KeySource = spec.parse().unwrap();
+            assert_eq!(parsed, source);
+            assert_eq!(parsed.to_string(), spec);
+        }
+        for spec in [
+            "",
+            "fd:",
+            "fd:three",
+            "env:",
+            "credential:../key",
+            "credential:..",
+            "command: ",
+        ] {
+            assert!(spec.parse::<KeySource>().is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn command() {
+        let source: KeySource = "command:echo first second".parse().unwrap();
+        assert_eq!(*source.read().unwrap(), b"first second\n");
+        let source: KeySource = "command:false".parse().unwrap();
+        assert!(source.read().is_err());
+    }
+
+    #[test]
+    fn fd() {
+        let (reader, writer) = pipe().unwrap();
+        write(writer, b"key").unwrap();
+        close(writer).unwrap();
+        let source = KeySource::Fd(reader);
+        // the second read gets the same contents, not the end of the pipe
+        assert_eq!(*source.read().unwrap(), b"key");
+        assert_eq!(*source.read().unwrap(), b"key");
+        close(reader).unwrap();
+    }
+}
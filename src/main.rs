@@ -22,26 +22,52 @@
 
 use std::{
     fmt, fs,
-    io::{self, Read as _, Write as _},
-    ops::Deref,
+    io::{self, Read, Write},
+    os::unix::io::RawFd,
     path::{Path, PathBuf},
 };
 
+use aes_gcm_siv::Aes256GcmSiv;
 use chacha20poly1305::{
-    aead::{Aead as _, Key, Nonce},
-    AeadCore, KeyInit as _, XChaCha20Poly1305 as Cipher,
+    aead::{Aead as _, AeadInPlace, Nonce, Payload},
+    KeyInit as _, XChaCha20Poly1305,
 };
-use clap::{CommandFactory as _, Parser, Subcommand};
-use color_eyre::eyre::{self, eyre, WrapErr as _};
-use indexmap::IndexMap;
+use clap::{Args, CommandFactory as _, Parser, Subcommand, ValueEnum};
+use color_eyre::eyre::{self, bail, ensure, eyre, WrapErr as _};
+use ed25519_dalek::SigningKey;
+use either::Either;
 use itertools::Itertools as _;
-use serde::{Deserialize, Serialize};
-use zeroize::Zeroize as _;
+use rand::{CryptoRng, RngCore};
+use zeroize::{Zeroize as _, Zeroizing};
 
-use crate::config::{Config, FileDesc};
+use crate::{
+    compress::{Compression, Decompressor},
+    config::{Config, FileDesc},
+    envelope::{
+        Algorithm, Body, Envelope, Header, ARMOR_LABEL, FLAG_PADDED, FLAG_SIGNED, FLAG_STREAM,
+        MAGIC,
+    },
+    key::{ArmoredKey, KeyFormat, Keyring},
+    keysource::KeySource,
+    pad::{Padded, Unpad},
+    passphrase::Passphrase,
+};
 
+mod age;
+mod armor;
+mod compress;
 mod config;
+mod envelope;
+mod key;
 mod keyarmor;
+mod keysource;
+mod pad;
+mod passphrase;
+mod shamir;
+mod sign;
+mod ssh;
+mod stream;
+mod x25519;
 
 #[derive(Subcommand)]
 enum Shell {
@@ -60,32 +86,254 @@ impl From<Shell> for clap_complete::Shell {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// The native format of this tool
+    Classified,
+    /// age v1, armored
+    Age,
+}
+
+#[derive(Args)]
+struct EncryptArgs {
+    /// Key file: a path, `fd:N` (an inherited file descriptor), `env:NAME` (an environment
+    /// variable), `credential:NAME` (a systemd credential) or `command:PROGRAM ARGS...` (the
+    /// output of a command); can be given multiple times to make the file decryptable with any of
+    /// the keys
+    #[clap(short, long, required_unless_present_any = ["recipient", "ssh_recipient"])]
+    key: Vec<KeySource>,
+    /// Public key (as printed by `gen-key --keypair` or `public-key`) to encrypt for; can be
+    /// given multiple times
+    #[clap(short, long)]
+    recipient: Vec<x25519::PublicKey>,
+    /// SSH ed25519 public key (e.g. a host key: "ssh-ed25519 AAAA...") to encrypt for; can be
+    /// given multiple times
+    #[clap(long)]
+    ssh_recipient: Vec<ssh::PublicKey>,
+    /// Output format; age files can be decrypted by `age`, agenix and friends
+    #[clap(long, value_enum, default_value_t = Format::Classified)]
+    format: Format,
+    /// Compress the data before encrypting it
+    #[clap(long, value_enum)]
+    compress: Option<Compression>,
+    /// Pad the data, so the size of the encrypted file only reveals roughly how large it is
+    #[clap(long)]
+    pad: bool,
+    /// Encryption algorithm; it's recorded in the file, so decrypting doesn't need to be told
+    #[clap(long, value_enum, default_value_t = Algorithm::XChaCha20Poly1305)]
+    algorithm: Algorithm,
+    /// Encrypt in fixed-size chunks, so large files are processed with bounded memory (age
+    /// files always are)
+    #[clap(long)]
+    stream: bool,
+    /// Write raw bytes instead of text armor (`decrypt` accepts both)
+    #[clap(long)]
+    binary: bool,
+    /// Label the file is meant for (e.g. its name in the config); it's authenticated and
+    /// checked by `batch`
+    #[clap(long)]
+    context: Option<String>,
+    /// Sign the file with this key file, proving who encrypted it (see `decrypt --verify`)
+    #[clap(long, value_name = "KEY")]
+    sign: Option<KeySource>,
+    #[clap(flatten)]
+    passphrase: PassphraseArgs,
+    /// File to encrypt, stdin if absent
+    file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct PassphraseArgs {
+    /// Read the passphrase of protected key files from this file descriptor instead of
+    /// prompting for it
+    #[clap(long = "passphrase-fd", value_name = "FD")]
+    fd: Option<RawFd>,
+}
+
+impl From<PassphraseArgs> for Passphrase {
+    fn from(args: PassphraseArgs) -> Self {
+        Self::new(args.fd)
+    }
+}
+
 #[allow(clippy::doc_markdown)]
 /// Simple encryption tool intended for use with NixOS
 #[derive(Parser)]
 enum Command {
     /// Generate a new encryption key and print it to stdout
     #[clap(display_order = 1)]
-    GenKey,
-    /// Encrypt file or stdin with given encryption key and print result to stdout (armored as
-    /// base64)
-    #[clap(display_order = 2)]
-    Encrypt {
-        /// Path to the key file
+    GenKey {
+        /// Also print the public key (to stderr), so others can encrypt for this key with
+        /// `encrypt --recipient`
+        #[clap(long)]
+        keypair: bool,
+        /// End the words with a standard BIP39 checksum instead of a CRC8, so BIP39 tools
+        /// (e.g. hardware backup devices) can check and store them
+        #[clap(long)]
+        bip39: bool,
+        /// Write the key with words from this list; the PGP list is meant for reading keys
+        /// aloud, e.g. over the phone (every list is recognized when reading keys)
+        #[clap(long, value_enum, default_value_t)]
+        wordlist: keyarmor::Wordlist,
+        /// Write the key as hex, base64 or 32 raw bytes instead of words
+        #[clap(long, value_enum, default_value_t)]
+        format: KeyFormat,
+        /// Add a `# label: ...` line to the key file, shown by `key-info`
+        #[clap(long)]
+        label: Option<String>,
+    },
+    /// Print the ID, format, header, checksum, word list and public keys of a key file
+    #[clap(display_order = 1)]
+    KeyInfo {
+        /// Key file, like `encrypt --key`
+        #[clap(short, long)]
+        key: KeySource,
+        #[clap(flatten)]
+        passphrase: PassphraseArgs,
+    },
+    /// Print the public key of a key file, for use with `encrypt --recipient`
+    #[clap(display_order = 1)]
+    PublicKey {
+        /// Key file, like `encrypt --key`
+        #[clap(short, long)]
+        key: KeySource,
+        /// Print an age recipient (`age1...`) instead
+        #[clap(long, value_enum, default_value_t = Format::Classified)]
+        format: Format,
+        /// Print the public key that verifies signatures made with `encrypt --sign` instead
+        #[clap(long, conflicts_with = "format")]
+        signing: bool,
+        #[clap(flatten)]
+        passphrase: PassphraseArgs,
+    },
+    /// Add, change or remove the passphrase of a key file and print the result to stdout
+    #[clap(display_order = 1)]
+    RewrapKey {
+        /// Key file, like `encrypt --key`
+        #[clap(short, long)]
+        key: KeySource,
+        #[clap(flatten)]
+        passphrase: PassphraseArgs,
+        /// Read the new passphrase from this file descriptor instead of prompting for it
+        #[clap(long, value_name = "FD")]
+        new_passphrase_fd: Option<RawFd>,
+        /// Remove the passphrase instead
+        #[clap(long, conflicts_with = "new_passphrase_fd")]
+        remove: bool,
+    },
+    /// Look for a mistyped word in key words (or a share) whose checksum doesn't match, and print
+    /// the single-word substitutions that fix it, likeliest first
+    #[clap(display_order = 1)]
+    RepairKey {
+        /// File with the words, stdin if absent
+        file: Option<PathBuf>,
+    },
+    /// Split a key into shares (for backups), any `threshold` of which restore it with
+    /// `combine-key`, and print them to stdout, one per line
+    #[clap(display_order = 1)]
+    SplitKey {
+        /// Key file, like `encrypt --key`
         #[clap(short, long)]
-        key: PathBuf,
-        /// File to encrypt, stdin if absent
+        key: KeySource,
+        /// How many shares are needed to restore the key
+        #[clap(long)]
+        threshold: u8,
+        /// How many shares to make
+        #[clap(long)]
+        shares: u8,
+        #[clap(flatten)]
+        passphrase: PassphraseArgs,
+    },
+    /// Restore a key from shares made by `split-key`, one per line, and print it to stdout
+    #[clap(display_order = 1)]
+    CombineKey {
+        /// Print the key with a BIP39 checksum, like `gen-key --bip39`
+        #[clap(long)]
+        bip39: bool,
+        /// Print the key with words from this list, like `gen-key --wordlist`
+        #[clap(long, value_enum, default_value_t)]
+        wordlist: keyarmor::Wordlist,
+        /// File with the shares, stdin if absent
+        file: Option<PathBuf>,
+    },
+    /// Write a secret (e.g. a recovery code or a wrapped key) as words, for writing it down or
+    /// reading it aloud, and print them to stdout; 16 to 32 bytes are standard BIP39 mnemonics
+    #[clap(display_order = 1)]
+    Armor {
+        /// Use words from this list
+        #[clap(long, value_enum, default_value_t)]
+        wordlist: keyarmor::Wordlist,
+        /// File with the secret, stdin if absent
+        file: Option<PathBuf>,
+    },
+    /// Decode words written by `armor` (or the words of a key) and write the secret to stdout
+    #[clap(display_order = 1)]
+    Unarmor {
+        /// Read words from this list; only needed if the words are valid in several lists,
+        /// which can happen when there are few of them
+        #[clap(long, value_enum)]
+        wordlist: Option<keyarmor::Wordlist>,
+        /// File with the words, stdin if absent
         file: Option<PathBuf>,
     },
+    /// Encrypt file or stdin with given encryption key and print result to stdout (armored as
+    /// text unless `--binary` is given)
+    #[clap(display_order = 2)]
+    Encrypt(EncryptArgs),
     #[clap(display_order = 3)]
-    /// Decrypt file that was previously encrypted with `encrypt` and print result to stdout
+    /// Decrypt file that was previously encrypted with `encrypt` (or age) and print result to
+    /// stdout
     Decrypt {
-        /// Path to the key file
+        /// Key file: a path, `fd:N` (an inherited file descriptor), `env:NAME` (an environment
+        /// variable), `credential:NAME` (a systemd credential) or `command:PROGRAM ARGS...` (the
+        /// output of a command); can be given multiple times, the key the file was encrypted for
+        /// is picked automatically. Not needed for passphrase-encrypted age files, whose
+        /// passphrase is read like that of protected key files.
         #[clap(short, long)]
-        key: PathBuf,
+        key: Vec<KeySource>,
+        /// Fail if the file was encrypted with a different context label
+        #[clap(long)]
+        context: Option<String>,
+        /// Fail unless the file is signed by this public key (as printed by `public-key
+        /// --signing`); can be given multiple times to accept any of them. Streams are
+        /// verified at the end, so their output is only trustworthy if the command succeeds.
+        #[clap(long, value_name = "PUBLIC_KEY")]
+        verify: Vec<sign::PublicKey>,
+        #[clap(flatten)]
+        passphrase: PassphraseArgs,
         /// File to decrypt, stdin if absent
         file: Option<PathBuf>,
     },
+    #[clap(display_order = 3)]
+    /// Re-encrypt files for another key, replacing them in place
+    Rekey {
+        /// Key file the files are encrypted for now, like `encrypt --key`; can be given multiple
+        /// times
+        #[clap(long, required = true)]
+        from: Vec<KeySource>,
+        /// Key file to encrypt the files for instead
+        #[clap(long)]
+        to: KeySource,
+        /// Public key to encrypt the files for besides `--to`; can be given multiple times. Files
+        /// don't say which public keys they're encrypted for, so all of them have to be given
+        /// again, or the files can't be rekeyed.
+        #[clap(short, long)]
+        recipient: Vec<x25519::PublicKey>,
+        /// SSH ed25519 public key to encrypt the files for besides `--to`, like `--recipient`
+        #[clap(long)]
+        ssh_recipient: Vec<ssh::PublicKey>,
+        /// Sign the rekeyed files with this key file; signatures can't be kept otherwise
+        #[clap(long, value_name = "KEY")]
+        sign: Option<KeySource>,
+        /// Rekey every `encrypted` file of this JSON/TOML config too
+        #[clap(long)]
+        config: Option<PathBuf>,
+        #[clap(flatten)]
+        passphrase: PassphraseArgs,
+        /// Files to rekey
+        #[clap(required_unless_present = "config")]
+        files: Vec<PathBuf>,
+    },
     #[clap(display_order = 4)]
     /// Decrypt multiple files to their target directories, according to JSON/TOML config
     Batch {
@@ -98,162 +346,932 @@ enum Command {
     Completions(Shell),
 }
 
-fn trim_newline(mut x: &[u8]) -> &[u8] {
-    while let Some((b'\n', start)) = x.split_last() {
-        x = start;
-    }
-    x
+fn maybe_stdin(file: Option<&Path>) -> eyre::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    open_input(file)?
+        .read_to_end(&mut buf)
+        .wrap_err("failed to read input")?;
+    Ok(buf)
 }
 
-fn maybe_stdin(file: Option<&Path>) -> eyre::Result<Vec<u8>> {
+fn open_input(file: Option<&Path>) -> eyre::Result<Either<fs::File, io::StdinLock<'static>>> {
     if let Some(file) = file {
-        fs::read(file).wrap_err("failed to read input file")
+        let file = fs::File::open(file).wrap_err("failed to open input file")?;
+        Ok(Either::Left(file))
     } else {
-        let mut buf = Vec::new();
-        io::stdin()
-            .read_to_end(&mut buf)
-            .wrap_err("failed to read stdin")?;
-        Ok(buf)
+        Ok(Either::Right(io::stdin().lock()))
     }
 }
 
-fn decrypt(filename: impl fmt::Debug, cipher: &Cipher, armored: &[u8]) -> eyre::Result<Vec<u8>> {
-    let encrypted_bytes =
-        base64::decode(trim_newline(armored)).wrap_err("failed to unarmor encrypted file")?;
-    let encrypted: Encrypted = serde_cbor::from_slice(&encrypted_bytes)
-        .wrap_err("failed to deserialize encrypted file")?;
-    cipher
-        .decrypt(&encrypted.nonce, &*encrypted.bytes)
-        .map_err(|_| eyre!("failed to decrypt {filename:?}"))
+// Keys given on the command line, named by their paths (or specs)
+fn load_keyring(sources: &[KeySource], passphrase: &mut Passphrase) -> eyre::Result<Keyring> {
+    Ok(Keyring::new(
+        sources
+            .iter()
+            .map(|source| {
+                let key = ArmoredKey::load(source, passphrase)?;
+                Ok((source.to_string(), key))
+            })
+            .collect::<eyre::Result<_>>()?,
+    ))
 }
 
-#[derive(Deserialize, Serialize)]
-struct Encrypted {
-    nonce: Nonce<Cipher>,
-    bytes: Vec<u8>,
+fn load_signer(
+    source: Option<&KeySource>,
+    passphrase: &mut Passphrase,
+) -> eyre::Result<Option<SigningKey>> {
+    source
+        .map(|source| Ok(sign::signing_key(&ArmoredKey::load(source, passphrase)?)))
+        .transpose()
 }
 
-struct ArmoredKey {
-    inner: Key<Cipher>,
+type Input = io::Chain<io::Cursor<Vec<u8>>, Either<fs::File, io::StdinLock<'static>>>;
+// classified files are either armored or binary
+type Decoded = Either<armor::Reader<Input>, Input>;
+type Verified = sign::Verifier<envelope::Payload<Decoded>>;
+
+#[allow(clippy::large_enum_variant)]
+enum Encrypted {
+    Classified(Envelope, Verified),
+    Age(age::Header, age::Payload<Input>),
 }
 
-impl fmt::Display for ArmoredKey {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            keyarmor::Words::new(self.inner.into()).words().format(" ")
-        )
+// An encrypted file along with the key for its payload
+#[allow(clippy::large_enum_variant)]
+enum Unlocked {
+    Classified(ArmoredKey, Envelope, Verified),
+    // a file without a key ID, which had to be decrypted to find its key
+    Opened(Envelope, Zeroizing<Vec<u8>>),
+    Age(age::FileKey, age::Payload<Input>),
+}
+
+impl Encrypted {
+    /// Unlock with the first of the keys named `wanted` that fits (or an automatically picked
+    /// one if there are none), also returning which one it was
+    fn unlock(
+        self,
+        keys: &Keyring,
+        wanted: &[String],
+        passphrase: &mut Passphrase,
+    ) -> eyre::Result<(Unlocked, usize)> {
+        match self {
+            Self::Classified(envelope, payload) if !names_key(&envelope) => {
+                try_keys(keys, wanted, envelope, payload)
+            }
+            Self::Classified(envelope, payload) => {
+                let (key, index) = first_fit(wanted, |name| keys.unlock(name, &envelope))?;
+                Ok((Unlocked::Classified(key, envelope, payload), index))
+            }
+            Self::Age(header, payload) => {
+                let (file_key, index) =
+                    first_fit(wanted, |name| header.unlock(keys, name, passphrase))?;
+                Ok((Unlocked::Age(file_key, payload), index))
+            }
+        }
     }
 }
 
-impl ArmoredKey {
-    fn new(inner: Key<Cipher>) -> Self {
-        Self { inner }
+// Whether the file says which key it's encrypted for, so it can be unlocked without decrypting it.
+// Only files from before there were key IDs don't, and they're never streams.
+fn names_key(envelope: &Envelope) -> bool {
+    envelope.body.key_id.is_some() || !envelope.body.recipients.is_empty() || envelope.is_stream()
+}
+
+// Decrypts a file without a key ID with each of the keys named `wanted` (or every key if there
+// are none) until one fits
+fn try_keys(
+    keys: &Keyring,
+    wanted: &[String],
+    envelope: Envelope,
+    mut payload: Verified,
+) -> eyre::Result<(Unlocked, usize)> {
+    let names: Vec<&str> = if wanted.is_empty() {
+        keys.candidates(None)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    } else {
+        wanted.iter().map(String::as_str).collect()
+    };
+    ensure!(!names.is_empty(), "no keys specified");
+    let mut encrypted = Vec::new();
+    payload
+        .read_to_end(&mut encrypted)
+        .wrap_err("failed to read encrypted file")?;
+    payload.finish()?;
+    for (index, name) in names.iter().enumerate() {
+        let key = keys.unlock(Some(name), &envelope)?;
+        let mut decrypted = Zeroizing::new(Vec::new());
+        if open_with(&key, &envelope, encrypted.as_slice(), &mut *decrypted).is_ok() {
+            // like `first_fit`, the index is only meaningful in `wanted`
+            let index = if wanted.is_empty() { 0 } else { index };
+            return Ok((Unlocked::Opened(envelope, decrypted), index));
+        }
+    }
+    bail!(
+        "authentication failed with every key: {:?}",
+        names.iter().format(", ")
+    )
+}
+
+// Files that name their key are unlocked with the first of the keys named `wanted` it's for
+fn first_fit<T>(
+    wanted: &[String],
+    mut unlock: impl FnMut(Option<&str>) -> eyre::Result<T>,
+) -> eyre::Result<(T, usize)> {
+    let Some((first, fallbacks)) = wanted.split_first() else {
+        return Ok((unlock(None)?, 0));
+    };
+    let err = match unlock(Some(first)) {
+        Ok(key) => return Ok((key, 0)),
+        Err(err) => err,
+    };
+    for (index, name) in fallbacks.iter().enumerate() {
+        if let Ok(key) = unlock(Some(name)) {
+            return Ok((key, index + 1));
+        }
+    }
+    if fallbacks.is_empty() {
+        Err(err)
+    } else {
+        Err(err.wrap_err(format!("none of the keys {wanted:?} fit")))
     }
+}
 
-    fn from_file(path: &Path) -> eyre::Result<Self> {
-        let armored = fs::read_to_string(path).wrap_err("failed to read key file")?;
-        let words: Vec<_> = armored.split_whitespace().collect();
-        let bytes = keyarmor::Words::from_words(
-            &words
-                .try_into()
-                .map_err(|v: Vec<_>| eyre!("wrong number of words: {} instead of 24", v.len()))?,
+impl Encrypted {
+    // binary files are read directly, armored ones through the decoder
+    fn is_binary(&self) -> bool {
+        matches!(self, Self::Classified(_, payload) if matches!(payload.get_ref(), Either::Left(Either::Right(_))))
+    }
+}
+
+impl Unlocked {
+    fn is_stream(&self) -> bool {
+        match self {
+            Self::Classified(_, envelope, _) => envelope.is_stream(),
+            Self::Opened(..) => false,
+            // age payloads are always chunked
+            Self::Age(..) => true,
+        }
+    }
+}
+
+fn open_encrypted(
+    filename: impl fmt::Debug,
+    file: Option<&Path>,
+    context: Option<&str>,
+    signers: &[sign::PublicKey],
+) -> eyre::Result<Encrypted> {
+    let mut input = open_input(file)?;
+    let mut head = Vec::new();
+    (&mut input)
+        .take(age::SNIFF_LEN as u64)
+        .read_to_end(&mut head)
+        .wrap_err_with(|| format!("failed to read {filename:?}"))?;
+    let encoding = age::Encoding::sniff(&head);
+    let binary = head.starts_with(MAGIC);
+    let input = io::Cursor::new(head).chain(input);
+
+    // age files have no context label, which is accepted just like for older files
+    if let Some(encoding) = encoding {
+        ensure!(
+            signers.is_empty(),
+            "refusing to decrypt {filename:?}: age files can't be signed"
+        );
+        let (header, payload) =
+            age::read(input, encoding).wrap_err_with(|| format!("failed to parse {filename:?}"))?;
+        return Ok(Encrypted::Age(header, payload));
+    }
+
+    let input = if binary {
+        Either::Right(input)
+    } else {
+        Either::Left(armor::Reader::new(input, ARMOR_LABEL))
+    };
+    let (envelope, payload) =
+        Envelope::read(input).wrap_err_with(|| format!("failed to parse {filename:?}"))?;
+    if let Some(context) = context {
+        envelope
+            .check_context(context)
+            .wrap_err_with(|| format!("refusing to decrypt {filename:?}"))?;
+    }
+    let payload = sign::Verifier::new(payload, &envelope)
+        .wrap_err_with(|| format!("failed to parse {filename:?}"))?;
+    // files signed by anyone are accepted if no signers are required
+    match payload.signer() {
+        _ if signers.is_empty() => {}
+        None => bail!("refusing to decrypt {filename:?}: file is not signed"),
+        Some(signer) => ensure!(
+            signers.contains(&signer),
+            "refusing to decrypt {filename:?}: file is signed by {signer}, which is not trusted"
+        ),
+    }
+    Ok(Encrypted::Classified(envelope, payload))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn encrypt(
+    key: &ArmoredKey,
+    header: Header,
+    body: Body,
+    signer: Option<&SigningKey>,
+    binary: bool,
+    input: impl Read,
+    output: impl Write,
+    mut rng: impl RngCore + CryptoRng,
+) -> eyre::Result<()> {
+    let compression = Compression::from_flags(header.flags)?;
+    let input = compress::compress(compression, input).wrap_err("failed to start compression")?;
+    let input = if header.flags & FLAG_PADDED != 0 {
+        Either::Left(Padded::new(input))
+    } else {
+        Either::Right(input)
+    };
+    // the flag follows the signer, e.g. when a signed file is rekeyed without one
+    let header = Header {
+        flags: if signer.is_some() {
+            header.flags | FLAG_SIGNED
+        } else {
+            header.flags & !FLAG_SIGNED
+        },
+        ..header
+    };
+    let body = Body {
+        salt: Some(key::generate_salt(&mut rng)),
+        signer: signer.map(|signer| sign::public(signer).to_bytes()),
+        ..body
+    };
+    let file_key = key.file_key(&body)?;
+    let out = if binary {
+        Either::Right(output)
+    } else {
+        Either::Left(armor::Writer::new(output, ARMOR_LABEL)?)
+    };
+    let mut out = sign::Signer::new(out, signer);
+    match header.algorithm {
+        Algorithm::XChaCha20Poly1305 => seal(
+            &XChaCha20Poly1305::new(&file_key),
+            header,
+            body,
+            input,
+            &mut out,
+            rng,
+        )?,
+        Algorithm::Aes256GcmSiv => seal(
+            &Aes256GcmSiv::new(&file_key),
+            header,
+            body,
+            input,
+            &mut out,
+            rng,
+        )?,
+    }
+    match out.finish()? {
+        Either::Left(armored) => armored.finish()?.flush()?,
+        Either::Right(mut binary) => binary.flush()?,
+    }
+    Ok(())
+}
+
+fn seal<C: AeadInPlace>(
+    cipher: &C,
+    header: Header,
+    body: Body,
+    mut input: impl Read,
+    mut out: impl Write,
+    mut rng: impl RngCore + CryptoRng,
+) -> eyre::Result<()> {
+    if header.flags & FLAG_STREAM != 0 {
+        let envelope = Envelope::new(
+            header,
+            Body {
+                nonce: stream::generate_prefix::<C>(&mut rng),
+                chunk_size: Some(stream::DEFAULT_CHUNK_SIZE),
+                ..body
+            },
+        )?;
+        out.write_all(&envelope.associated_data)?;
+        stream::encrypt(cipher, &envelope, input, &mut out)
+    } else {
+        let nonce = C::generate_nonce(&mut rng);
+        let envelope = Envelope::new(
+            header,
+            Body {
+                nonce: nonce.to_vec(),
+                ..body
+            },
+        )?;
+        let mut plaintext = Vec::new();
+        input
+            .read_to_end(&mut plaintext)
+            .wrap_err("failed to read input")?;
+        let bytes = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: &envelope.associated_data,
+                },
+            )
+            .map_err(|_| eyre!("failed to encrypt"))?;
+        plaintext.zeroize();
+        out.write_all(&envelope.associated_data)?;
+        out.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+fn decrypt(filename: impl fmt::Debug, unlocked: Unlocked, output: impl Write) -> eyre::Result<()> {
+    let (envelope, source) = match unlocked {
+        Unlocked::Classified(key, envelope, payload) => (envelope, Either::Left((key, payload))),
+        Unlocked::Opened(envelope, decrypted) => (envelope, Either::Right(decrypted)),
+        Unlocked::Age(file_key, payload) => {
+            return age::decrypt(&file_key, payload, output)
+                .wrap_err_with(|| format!("failed to decrypt {filename:?}"));
+        }
+    };
+    let compression = Compression::from_flags(envelope.header.flags)?;
+    let output =
+        Decompressor::new(compression, output).wrap_err("failed to start decompression")?;
+    let mut output = Unpad::new(output, envelope.header.flags & FLAG_PADDED != 0);
+    match source {
+        Either::Left((key, mut payload)) => {
+            open_with(&key, &envelope, &mut payload, &mut output).and_then(|()| payload.finish())
+        }
+        Either::Right(decrypted) => output
+            .write_all(&decrypted)
+            .wrap_err("failed to write decrypted data"),
+    }
+    .and_then(|()| output.finish())
+    .and_then(|output| output.finish().wrap_err("failed to write decrypted data"))
+    .wrap_err_with(|| format!("failed to decrypt {filename:?}"))
+}
+
+fn open_with(
+    key: &ArmoredKey,
+    envelope: &Envelope,
+    payload: impl Read,
+    output: impl Write,
+) -> eyre::Result<()> {
+    match envelope.header.algorithm {
+        Algorithm::XChaCha20Poly1305 => {
+            open(&XChaCha20Poly1305::new(key), envelope, payload, output)
+        }
+        Algorithm::Aes256GcmSiv => open(&Aes256GcmSiv::new(key), envelope, payload, output),
+    }
+}
+
+fn open<C: AeadInPlace>(
+    cipher: &C,
+    envelope: &Envelope,
+    mut payload: impl Read,
+    mut output: impl Write,
+) -> eyre::Result<()> {
+    if envelope.is_stream() {
+        return stream::decrypt(cipher, envelope, payload, output);
+    }
+    ensure!(
+        envelope.body.nonce.len() == Nonce::<C>::default().len(),
+        "wrong nonce length"
+    );
+    let mut encrypted = Vec::new();
+    payload
+        .read_to_end(&mut encrypted)
+        .wrap_err("failed to read encrypted file")?;
+    let mut decrypted = cipher
+        .decrypt(
+            Nonce::<C>::from_slice(&envelope.body.nonce),
+            Payload {
+                msg: &encrypted,
+                aad: &envelope.associated_data,
+            },
         )
-        .wrap_err("failed to decode key")?
-        .bytes();
-        Ok(Self {
-            inner: Key::<Cipher>::from(bytes),
+        .map_err(|_| eyre!("authentication failed"))?;
+    let res = output
+        .write_all(&decrypted)
+        .wrap_err("failed to write decrypted data");
+    decrypted.zeroize();
+    res
+}
+
+fn batch(config: Option<&Path>) -> eyre::Result<()> {
+    let config = Config::parse(&maybe_stdin(config)?)?;
+    let mut passphrase = Passphrase::unavailable("passphrases are not supported in batch mode");
+    let keys = Keyring::new(
+        config
+            .keys
+            .iter()
+            .map(|(name, source)| Ok((name.clone(), ArmoredKey::load(source, &mut passphrase)?)))
+            .collect::<eyre::Result<_>>()?,
+    );
+
+    // Everything except streams is decrypted before any file is created,
+    // streams are written chunk by chunk
+    let decrypted: Vec<(&FileDesc, &str, Either<Vec<u8>, _>)> = config
+        .files
+        .iter()
+        .map(|(name, file)| {
+            let wanted = config.keys_for(file);
+            let (unlocked, index) =
+                open_encrypted(
+                    &file.encrypted,
+                    Some(&file.encrypted),
+                    Some(name),
+                    &config.trusted_signers,
+                )?
+                    .unlock(&keys, wanted, &mut passphrase)
+                    .wrap_err_with(|| format!("can't decrypt {name:?}"))?;
+            if index > 0 {
+                eprintln!(
+                    "warning: {name:?} is still encrypted for deprecated key {:?}, rekey it for {:?}",
+                    wanted[index], wanted[0]
+                );
+            }
+            if unlocked.is_stream() {
+                return Ok((file, name.as_str(), Either::Right(unlocked)));
+            }
+            let mut decrypted = Vec::new();
+            decrypt(&file.encrypted, unlocked, &mut decrypted)?;
+            Ok((file, name.as_str(), Either::Left(decrypted)))
+        })
+        .collect::<eyre::Result<_>>()?;
+
+    for (file, name, contents) in decrypted {
+        let path = config.target_dir.join(name);
+        match contents {
+            Either::Left(mut contents) => {
+                file.create(&path, |out| {
+                    out.write_all(&contents)
+                        .wrap_err("failed to write to decrypted file")
+                })?;
+                contents.zeroize();
+            }
+            Either::Right(unlocked) => {
+                file.create(&path, |out| decrypt(&file.encrypted, unlocked, out))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Decrypts `path` and encrypts it again for `to` (and the other recipients in `public`), keeping
+// its settings. Files with recipients get a new data key, so the old key can't unwrap it. Also
+// returns whether a signature was dropped, because there's no `signer`.
+fn rekey_file(
+    path: &Path,
+    from: &Keyring,
+    to: &ArmoredKey,
+    public: &[x25519::PublicKey],
+    signer: Option<&SigningKey>,
+    mut rng: impl RngCore + CryptoRng,
+) -> eyre::Result<(Vec<u8>, bool)> {
+    let encrypted = open_encrypted(path, Some(path), None, &[])?;
+    let binary = encrypted.is_binary();
+    let Encrypted::Classified(envelope, payload) = &encrypted else {
+        bail!("age files can't be rekeyed, encrypt them again with `encrypt --format age`");
+    };
+    let dropped_signature = payload.signer().is_some() && signer.is_none();
+    let (old_header, old_body) = (envelope.header, envelope.body.clone());
+    let mut plaintext = Zeroizing::new(Vec::new());
+    let (unlocked, _) = encrypted.unlock(
+        from,
+        &[],
+        &mut Passphrase::unavailable("age files can't be rekeyed"),
+    )?;
+    decrypt(path, unlocked, &mut *plaintext)?;
+
+    let header = Header {
+        flags: old_header.flags,
+        ..Header::new(old_header.algorithm)
+    };
+    let (key, body) = if !old_body.recipients.is_empty() || !public.is_empty() {
+        let data_key = ArmoredKey::new(XChaCha20Poly1305::generate_key(&mut rng));
+        let body = Body {
+            context: old_body.context,
+            recipients: from.rewrap(&old_body.recipients, &data_key, to, public, &mut rng)?,
+            ..Body::default()
+        };
+        (data_key, body)
+    } else {
+        let body = Body {
+            context: old_body.context,
+            key_id: Some(to.id().as_bytes().to_vec()),
+            ..Body::default()
+        };
+        (ArmoredKey::new(**to), body)
+    };
+    let mut output = Vec::new();
+    encrypt(
+        &key,
+        header,
+        body,
+        signer,
+        binary,
+        plaintext.as_slice(),
+        &mut output,
+        rng,
+    )?;
+    Ok((output, dropped_signature))
+}
+
+// Writes a temporary file next to `path` and renames it over, so `path` is never half-written.
+// The name is random, so a file left behind by an interrupted run doesn't get in the way.
+fn replace_file(
+    path: &Path,
+    contents: &[u8],
+    mut rng: impl RngCore + CryptoRng,
+) -> eyre::Result<()> {
+    let permissions = fs::metadata(path)
+        .wrap_err("failed to read file metadata")?
+        .permissions();
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{:016x}.rekey", rng.next_u64()));
+    let temporary = PathBuf::from(temporary);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temporary)
+        .wrap_err_with(|| format!("failed to create {}", temporary.display()))?;
+    let res = file
+        .write_all(contents)
+        .and_then(|()| file.set_permissions(permissions))
+        .and_then(|()| file.sync_all())
+        .and_then(|()| fs::rename(&temporary, path));
+    if res.is_err() {
+        drop(fs::remove_file(&temporary));
+    }
+    res.wrap_err_with(|| format!("failed to replace {}", path.display()))
+}
+
+fn rekey(
+    from: &Keyring,
+    to: &ArmoredKey,
+    public: &[x25519::PublicKey],
+    signer: Option<&SigningKey>,
+    files: &[PathBuf],
+    mut rng: impl RngCore + CryptoRng,
+) -> eyre::Result<()> {
+    // nothing is replaced unless every file can be rekeyed
+    let rekeyed = files
+        .iter()
+        .map(|path| {
+            rekey_file(path, from, to, public, signer, &mut rng)
+                .wrap_err_with(|| format!("can't rekey {}", path.display()))
         })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    for (path, (contents, dropped_signature)) in files.iter().zip(rekeyed) {
+        if dropped_signature {
+            eprintln!(
+                "warning: {} was signed, the signature is dropped unless `--sign` is given",
+                path.display()
+            );
+        }
+        replace_file(path, &contents, &mut rng)?;
+        eprintln!("rekeyed {}", path.display());
     }
+    Ok(())
 }
 
-impl Deref for ArmoredKey {
-    type Target = Key<Cipher>;
+fn repair_key(file: Option<&Path>) -> eyre::Result<()> {
+    const SHOWN: usize = 10;
+
+    let input = Zeroizing::new(maybe_stdin(file)?);
+    let input = std::str::from_utf8(&input).wrap_err("words are not valid UTF-8")?;
+    // shares start with a header
+    let words: Vec<_> = input
+        .rsplit_once(':')
+        .map_or(input, |(_, words)| words)
+        .split_whitespace()
+        .collect();
+    let err = match keyarmor::Words::from_words(&words, None) {
+        Ok(parsed) => {
+            eprintln!(
+                "the words are fine ({} checksum, {} word list)",
+                parsed.checksum(),
+                parsed.wordlist()
+            );
+            return Ok(());
+        }
+        Err(err) => err,
+    };
+    let repairs = keyarmor::repair(&words);
+    if repairs.is_empty() {
+        return Err(err.wrap_err("no single word can be replaced to make the checksum valid"));
+    }
+    for repair in repairs.iter().take(SHOWN) {
+        println!(
+            "word {}: {:?} -> {:?} ({} checksum)",
+            repair.position + 1,
+            words[repair.position],
+            repair.word,
+            repair.checksum,
+        );
+    }
+    if repairs.len() > SHOWN {
+        println!("... and {} less likely ones", repairs.len() - SHOWN);
+    }
+    Ok(())
+}
+
+fn checksum(bip39: bool) -> keyarmor::Checksum {
+    if bip39 {
+        keyarmor::Checksum::Bip39
+    } else {
+        keyarmor::Checksum::Crc8
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.inner
+// Shares that can't be parsed are skipped, as long as enough others are left
+fn combine_key(
+    checksum: keyarmor::Checksum,
+    wordlist: keyarmor::Wordlist,
+    file: Option<&Path>,
+) -> eyre::Result<()> {
+    let input = Zeroizing::new(maybe_stdin(file)?);
+    let input = std::str::from_utf8(&input).wrap_err("shares are not valid UTF-8")?;
+    let mut shares = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.parse::<shamir::Share>() {
+            Ok(share) => shares.push(share),
+            Err(err) => eprintln!("warning: skipping line {}: {err:#}", number + 1),
+        }
     }
+    let (key, corrupt) = shamir::combine(&shares)?;
+    for index in corrupt {
+        eprintln!("warning: share {index} is corrupt");
+    }
+    println!("{}", key.with_checksum(checksum).with_wordlist(wordlist));
+    Ok(())
+}
+
+fn armor(wordlist: keyarmor::Wordlist, file: Option<&Path>) -> eyre::Result<()> {
+    let input = Zeroizing::new(maybe_stdin(file)?);
+    let words = keyarmor::Words::new(&input, keyarmor::Checksum::Bip39, wordlist)?;
+    println!("{words}");
+    Ok(())
 }
 
-impl Drop for ArmoredKey {
-    fn drop(&mut self) {
-        self.inner.zeroize();
+fn unarmor(wordlist: Option<keyarmor::Wordlist>, file: Option<&Path>) -> eyre::Result<()> {
+    let input = Zeroizing::new(maybe_stdin(file)?);
+    let input = std::str::from_utf8(&input).wrap_err("words are not valid UTF-8")?;
+    let words: Vec<_> = input.split_whitespace().collect();
+    let words = keyarmor::Words::from_words(&words, wordlist)?;
+    io::stdout()
+        .lock()
+        .write_all(words.bytes())
+        .wrap_err("failed to write output")?;
+    Ok(())
+}
+
+fn encrypt_command(args: EncryptArgs, mut rng: impl RngCore + CryptoRng) -> eyre::Result<()> {
+    let EncryptArgs {
+        key,
+        mut recipient,
+        ssh_recipient,
+        format,
+        algorithm,
+        compress,
+        pad,
+        stream,
+        binary,
+        context,
+        sign,
+        passphrase,
+        file,
+    } = args;
+    let mut passphrase = Passphrase::from(passphrase);
+    let keys = key
+        .iter()
+        .map(|source| ArmoredKey::load(source, &mut passphrase))
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let signer = load_signer(sign.as_ref(), &mut passphrase)?;
+    if format == Format::Age {
+        ensure!(context.is_none(), "age files can't have a context label");
+        ensure!(signer.is_none(), "age files can't be signed");
+        ensure!(
+            algorithm == Algorithm::XChaCha20Poly1305,
+            "age files are always encrypted with ChaCha20-Poly1305"
+        );
+        ensure!(
+            compress.is_none() && !pad,
+            "age files can't be compressed or padded"
+        );
+        let recipients: Vec<_> = keys
+            .iter()
+            .map(|key| match key.ssh_public() {
+                Some(public) => age::Recipient::Ssh(public.clone()),
+                None => age::Recipient::X25519(x25519::public(key)),
+            })
+            .chain(recipient.into_iter().map(age::Recipient::X25519))
+            .chain(ssh_recipient.into_iter().map(age::Recipient::Ssh))
+            .collect();
+        let input = open_input(file.as_deref())?;
+        if binary {
+            age::encrypt(&recipients, input, io::stdout().lock(), &mut rng)?;
+        } else {
+            age::encrypt_armored(&recipients, input, io::stdout().lock(), &mut rng)?;
+        }
+        return Ok(());
+    }
+    for public in &ssh_recipient {
+        recipient.push(public.to_x25519()?);
     }
+    let (data_key, body) = if let ([key], []) = (keys.as_slice(), recipient.as_slice()) {
+        let body = Body {
+            context,
+            key_id: Some(key.id().as_bytes().to_vec()),
+            ..Body::default()
+        };
+        (ArmoredKey::new(**key), body)
+    } else {
+        let data_key = ArmoredKey::new(XChaCha20Poly1305::generate_key(&mut rng));
+        let mut recipients = keys
+            .iter()
+            .map(|key| key.wrap(&data_key, &mut rng))
+            .collect::<eyre::Result<Vec<_>>>()?;
+        for public in &recipient {
+            recipients.push(public.wrap(&data_key, &mut rng)?);
+        }
+        let body = Body {
+            context,
+            recipients,
+            ..Body::default()
+        };
+        (data_key, body)
+    };
+    let mut flags = compress.map_or(0, Compression::flag);
+    if stream {
+        flags |= FLAG_STREAM;
+    }
+    if pad {
+        flags |= FLAG_PADDED;
+    }
+    let header = Header {
+        flags,
+        ..Header::new(algorithm)
+    };
+    encrypt(
+        &data_key,
+        header,
+        body,
+        signer.as_ref(),
+        binary,
+        open_input(file.as_deref())?,
+        io::stdout().lock(),
+        &mut rng,
+    )?;
+    Ok(())
 }
 
+#[allow(clippy::too_many_lines)]
 fn main() -> eyre::Result<()> {
     color_eyre::install()?;
 
     let mut rng = rand::thread_rng();
     match Command::parse() {
-        Command::GenKey => {
-            let key = ArmoredKey::new(Cipher::generate_key(rng));
-            println!("{key}");
+        Command::GenKey {
+            keypair,
+            bip39,
+            wordlist,
+            format,
+            label,
+        } => {
+            let mut key = ArmoredKey::new(XChaCha20Poly1305::generate_key(rng))
+                .with_checksum(checksum(bip39))
+                .with_wordlist(wordlist)
+                .with_format(format);
+            if let Some(label) = label {
+                ensure!(format != KeyFormat::Raw, "raw key files can't have a label");
+                ensure!(!label.contains('\n'), "label can't have line breaks");
+                key = key.with_header("label", &label);
+            }
+            io::stdout()
+                .lock()
+                .write_all(&key.encode())
+                .wrap_err("failed to write key")?;
+            if keypair {
+                eprintln!("public key: {}", x25519::public(&key));
+            }
         }
-        Command::Encrypt { key, file } => {
-            let cipher = Cipher::new(&*ArmoredKey::from_file(&key)?);
-            let nonce = Cipher::generate_nonce(&mut rng);
-            let mut plaintext = maybe_stdin(file.as_deref())?;
-            let bytes = cipher
-                .encrypt(&nonce, plaintext.as_slice())
-                .map_err(|_| eyre!("failed to encrypt"))?;
-            let encrypted = Encrypted { nonce, bytes };
-            let cbor = serde_cbor::to_vec(&encrypted)?;
-            let mut out = io::stdout().lock();
-            out.write_all(base64::encode(cbor.as_slice()).as_bytes())?;
-            out.write_all(b"\n")?;
-            plaintext.zeroize();
+        Command::PublicKey {
+            key,
+            format,
+            signing,
+            passphrase,
+        } => {
+            let mut passphrase = Passphrase::from(passphrase);
+            let key = ArmoredKey::load(&key, &mut passphrase)?;
+            if signing {
+                println!("{}", sign::public(&sign::signing_key(&key)));
+                return Ok(());
+            }
+            let public = x25519::public(&key);
+            match format {
+                Format::Classified => println!("{public}"),
+                Format::Age => println!("{}", public.to_age()),
+            }
+        }
+        Command::RewrapKey {
+            key,
+            passphrase,
+            new_passphrase_fd,
+            remove,
+        } => {
+            let contents = passphrase::read_key_file(&key, &mut Passphrase::from(passphrase))?;
+            ArmoredKey::parse(&contents).wrap_err("not a valid key file")?;
+            if remove {
+                io::stdout()
+                    .lock()
+                    .write_all(&contents)
+                    .wrap_err("failed to write key")?;
+            } else {
+                let new_passphrase = Passphrase::new(new_passphrase_fd).get_new()?;
+                print!("{}", passphrase::protect(&contents, &new_passphrase, rng)?);
+            }
         }
-        Command::Decrypt { key, file } => {
-            let cipher = Cipher::new(&*ArmoredKey::from_file(&key)?);
-            let armored = maybe_stdin(file.as_deref())?;
-            let mut decrypted = decrypt(
-                file.as_deref().unwrap_or_else(|| "-".as_ref()),
-                &cipher,
-                &armored,
-            )?;
-            io::stdout().write_all(&decrypted)?;
-            decrypted.zeroize();
+        Command::SplitKey {
+            key,
+            threshold,
+            shares,
+            passphrase,
+        } => {
+            let key = ArmoredKey::load(&key, &mut Passphrase::from(passphrase))?;
+            let shares = shamir::split(&key, threshold, shares, rng)?;
+            println!("{}", shares.iter().format("\n"));
         }
-        Command::Batch { config } => {
-            let config = Config::parse(&maybe_stdin(config.as_deref())?)?;
-            let keys: IndexMap<&str, Cipher> = config
-                .keys
-                .iter()
-                .map(|(name, path)| {
-                    Ok((
-                        name.as_str(),
-                        Cipher::new(&*ArmoredKey::from_file(path.as_ref())?),
-                    ))
-                })
-                .collect::<eyre::Result<_>>()?;
-
-            let decrypted: Vec<(&FileDesc, &str, Vec<u8>)> = config
-                .files
-                .iter()
-                .map(|(name, file)| {
-                    let cipher = match &file.key {
-                        Some(key) => keys
-                            .get(key.as_str())
-                            .ok_or_else(|| eyre!("key {key:?} is not configured"))?,
-                        None => keys.first().ok_or_else(|| eyre!("no keys specified"))?.1,
-                    };
-                    let decrypted = decrypt(
-                        &file.encrypted,
-                        cipher,
-                        &maybe_stdin(Some(&file.encrypted))?,
-                    )?;
-                    Ok((file, name.as_str(), decrypted))
-                })
-                .collect::<eyre::Result<_>>()?;
-
-            for (file, name, mut contents) in decrypted {
-                let path = config.target_dir.join(name);
-                file.create(&path, &contents)?;
-                contents.zeroize();
+        Command::KeyInfo { key, passphrase } => {
+            let key = ArmoredKey::load(&key, &mut Passphrase::from(passphrase))?;
+            println!("id: {}", key.id());
+            match key.format() {
+                Some(format) => println!("format: {format}"),
+                None => println!("format: imported from age or SSH"),
+            }
+            for (name, value) in key.header() {
+                println!("# {name}: {value}");
             }
+            if let Some(checksum) = key.checksum() {
+                println!("checksum: {checksum}");
+            }
+            if let Some(wordlist) = key.wordlist() {
+                println!("word list: {wordlist}");
+            }
+            println!("public key: {}", x25519::public(&key));
+            println!("signing key: {}", sign::public(&sign::signing_key(&key)));
         }
+        Command::RepairKey { file } => repair_key(file.as_deref())?,
+        Command::CombineKey {
+            bip39,
+            wordlist,
+            file,
+        } => combine_key(checksum(bip39), wordlist, file.as_deref())?,
+        Command::Armor { wordlist, file } => armor(wordlist, file.as_deref())?,
+        Command::Unarmor { wordlist, file } => unarmor(wordlist, file.as_deref())?,
+        Command::Encrypt(args) => encrypt_command(args, &mut rng)?,
+        Command::Decrypt {
+            key,
+            context,
+            verify,
+            passphrase,
+            file,
+        } => {
+            let mut passphrase = Passphrase::from(passphrase);
+            let keys = load_keyring(&key, &mut passphrase)?;
+            let filename = file.as_deref().unwrap_or_else(|| "-".as_ref());
+            let (unlocked, _) =
+                open_encrypted(filename, file.as_deref(), context.as_deref(), &verify)?.unlock(
+                    &keys,
+                    &[],
+                    &mut passphrase,
+                )?;
+            decrypt(filename, unlocked, io::stdout().lock())?;
+        }
+        Command::Rekey {
+            from,
+            to,
+            mut recipient,
+            ssh_recipient,
+            sign,
+            config,
+            passphrase,
+            mut files,
+        } => {
+            if let Some(config) = config {
+                let config = Config::parse(&maybe_stdin(Some(&config))?)?;
+                files.extend(config.files.into_values().map(|file| file.encrypted));
+            }
+            let mut passphrase = Passphrase::from(passphrase);
+            let from = load_keyring(&from, &mut passphrase)?;
+            let to = ArmoredKey::load(&to, &mut passphrase)?;
+            let signer = load_signer(sign.as_ref(), &mut passphrase)?;
+            for public in &ssh_recipient {
+                recipient.push(public.to_x25519()?);
+            }
+            rekey(&from, &to, &recipient, signer.as_ref(), &files, &mut rng)?;
+        }
+        Command::Batch { config } => batch(config.as_deref())?,
         Command::Completions(shell) => {
             clap_complete::generate(
                 clap_complete::Shell::from(shell),
@@ -266,3 +1284,282 @@ fn main() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::{
+        env, fs,
+        path::{Path, PathBuf},
+        process,
+    };
+
+    use chacha20poly1305::{aead::Aead as _, KeyInit as _, XChaCha20Poly1305};
+    use color_eyre::eyre;
+    use ed25519_dalek::SigningKey;
+    use indexmap::IndexMap;
+    use serde::Serialize;
+
+    use super::{
+        batch, decrypt, encrypt, open_encrypted, rekey_file, sign, x25519, Algorithm, ArmoredKey,
+        Body, Header, Keyring, Passphrase,
+    };
+
+    #[derive(Serialize)]
+    struct Legacy {
+        nonce: [u8; 24],
+        bytes: Vec<u8>,
+    }
+
+    // Files from before there were key IDs fall back to deprecated keys while rotating
+    #[test]
+    fn legacy_fallback() {
+        let new = ArmoredKey::new([1; 32].into());
+        let old = ArmoredKey::new([2; 32].into());
+        let nonce = [3; 24];
+        let bytes = XChaCha20Poly1305::new(&old)
+            .encrypt(&nonce.into(), b"top secret".as_slice())
+            .unwrap();
+        let path = env::temp_dir().join(format!("classified-legacy-{}.enc", process::id()));
+        fs::write(
+            &path,
+            base64::encode(serde_cbor::to_vec(&Legacy { nonce, bytes }).unwrap()),
+        )
+        .unwrap();
+
+        let keys = Keyring::new(IndexMap::from([
+            ("new".to_owned(), new),
+            ("old".to_owned(), old),
+        ]));
+        let mut passphrase = Passphrase::unavailable("no passphrases here");
+        for (wanted, expected) in [(vec!["new", "old"], 1), (vec![], 0), (vec!["old"], 0)] {
+            let wanted: Vec<_> = wanted.into_iter().map(str::to_owned).collect();
+            let (unlocked, index) = open_encrypted(&path, Some(&path), None, &[])
+                .unwrap()
+                .unlock(&keys, &wanted, &mut passphrase)
+                .unwrap();
+            assert_eq!(index, expected);
+            let mut decrypted = Vec::new();
+            decrypt(&path, unlocked, &mut decrypted).unwrap();
+            assert_eq!(decrypted, b"top secret");
+        }
+        let err = open_encrypted(&path, Some(&path), None, &[])
+            .unwrap()
+            .unlock(&keys, &["new".to_owned()], &mut passphrase);
+        assert!(err.is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("classified-{name}-{}.enc", process::id()))
+    }
+
+    fn key(byte: u8) -> ArmoredKey {
+        ArmoredKey::new([byte; 32].into())
+    }
+
+    fn keyring(keys: &[&ArmoredKey]) -> Keyring {
+        Keyring::new(
+            keys.iter()
+                .enumerate()
+                .map(|(index, key)| (index.to_string(), ArmoredKey::new(***key)))
+                .collect(),
+        )
+    }
+
+    fn encrypt_file(path: &Path, key: &ArmoredKey, body: Body, signer: Option<&SigningKey>) {
+        let mut output = Vec::new();
+        let header = Header::new(Algorithm::XChaCha20Poly1305);
+        encrypt(
+            key,
+            header,
+            body,
+            signer,
+            false,
+            b"top secret".as_slice(),
+            &mut output,
+            rand::thread_rng(),
+        )
+        .unwrap();
+        fs::write(path, output).unwrap();
+    }
+
+    fn decrypt_file(
+        path: &Path,
+        key: &ArmoredKey,
+        context: Option<&str>,
+        signers: &[sign::PublicKey],
+    ) -> eyre::Result<Vec<u8>> {
+        let (unlocked, _) = open_encrypted(path, Some(path), context, signers)?.unlock(
+            &keyring(&[key]),
+            &[],
+            &mut Passphrase::unavailable("no passphrases here"),
+        )?;
+        let mut decrypted = Vec::new();
+        decrypt(path, unlocked, &mut decrypted)?;
+        Ok(decrypted)
+    }
+
+    fn rekey_in_place(
+        path: &Path,
+        from: &ArmoredKey,
+        to: &ArmoredKey,
+        public: &[x25519::PublicKey],
+        signer: Option<&SigningKey>,
+    ) -> bool {
+        let (rekeyed, dropped_signature) = rekey_file(
+            path,
+            &keyring(&[from]),
+            to,
+            public,
+            signer,
+            rand::thread_rng(),
+        )
+        .unwrap();
+        fs::write(path, rekeyed).unwrap();
+        dropped_signature
+    }
+
+    #[test]
+    fn rekey_single_key() {
+        let (old, new) = (key(1), key(2));
+        let path = temp_path("rekey-single");
+        let body = Body {
+            key_id: Some(old.id().as_bytes().to_vec()),
+            ..Body::default()
+        };
+        encrypt_file(&path, &old, body, None);
+
+        assert!(!rekey_in_place(&path, &old, &new, &[], None));
+        assert_eq!(decrypt_file(&path, &new, None, &[]).unwrap(), b"top secret");
+        assert!(decrypt_file(&path, &old, None, &[]).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rekey_recipients() {
+        let (old, new, other) = (key(1), key(2), key(3));
+        let path = temp_path("rekey-recipients");
+        let data_key = key(4);
+        let body = Body {
+            recipients: vec![
+                old.wrap(&data_key, rand::thread_rng()).unwrap(),
+                x25519::public(&other)
+                    .wrap(&data_key, rand::thread_rng())
+                    .unwrap(),
+            ],
+            ..Body::default()
+        };
+        encrypt_file(&path, &data_key, body, None);
+
+        // the other recipient can't be kept without its public key
+        assert!(rekey_file(
+            &path,
+            &keyring(&[&old]),
+            &new,
+            &[],
+            None,
+            rand::thread_rng()
+        )
+        .is_err());
+        rekey_in_place(&path, &old, &new, &[x25519::public(&other)], None);
+        assert_eq!(decrypt_file(&path, &new, None, &[]).unwrap(), b"top secret");
+        assert_eq!(
+            decrypt_file(&path, &other, None, &[]).unwrap(),
+            b"top secret"
+        );
+        assert!(decrypt_file(&path, &old, None, &[]).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rekey_context() {
+        let (old, new) = (key(1), key(2));
+        let path = temp_path("rekey-context");
+        let body = Body {
+            context: Some("db".to_owned()),
+            key_id: Some(old.id().as_bytes().to_vec()),
+            ..Body::default()
+        };
+        encrypt_file(&path, &old, body, None);
+
+        rekey_in_place(&path, &old, &new, &[], None);
+        assert_eq!(
+            decrypt_file(&path, &new, Some("db"), &[]).unwrap(),
+            b"top secret"
+        );
+        assert!(decrypt_file(&path, &new, Some("web"), &[]).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rekey_signed() {
+        let (old, new) = (key(1), key(2));
+        let signer = sign::signing_key(&key(3));
+        let path = temp_path("rekey-signed");
+        let body = Body {
+            key_id: Some(old.id().as_bytes().to_vec()),
+            ..Body::default()
+        };
+        encrypt_file(&path, &old, body, Some(&signer));
+        let signers = [sign::public(&signer)];
+        assert!(decrypt_file(&path, &old, None, &signers).is_ok());
+
+        // without a signer the signature is dropped, with a warning
+        assert!(rekey_in_place(&path, &old, &new, &[], None));
+        assert!(decrypt_file(&path, &new, None, &signers).is_err());
+        assert_eq!(decrypt_file(&path, &new, None, &[]).unwrap(), b"top secret");
+
+        assert!(!rekey_in_place(&path, &new, &old, &[], Some(&signer)));
+        assert_eq!(
+            decrypt_file(&path, &old, None, &signers).unwrap(),
+            b"top secret"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    // The entry name is the context a file has to be encrypted with
+    #[test]
+    fn batch_context() {
+        let key = key(1);
+        let key_path = temp_path("batch-key");
+        fs::write(&key_path, key.encode()).unwrap();
+        let path = temp_path("batch-context");
+        let body = Body {
+            context: Some("B".to_owned()),
+            key_id: Some(key.id().as_bytes().to_vec()),
+            ..Body::default()
+        };
+        encrypt_file(&path, &key, body, None);
+        let target_dir = env::temp_dir().join(format!("classified-batch-{}", process::id()));
+        fs::create_dir(&target_dir).unwrap();
+
+        let config_path = temp_path("batch-config");
+        for name in ["A", "B"] {
+            let config = serde_json::json!({
+                "targetDir": target_dir,
+                "keys": { "key": key_path },
+                "files": { name: { "key": "key", "encrypted": path } },
+            });
+            fs::write(&config_path, config.to_string()).unwrap();
+            let res = batch(Some(&config_path));
+            if name == "A" {
+                let err = res.unwrap_err();
+                assert!(
+                    err.chain()
+                        .any(|err| err.to_string() == r#"file was encrypted for "B", not "A""#),
+                    "{err:?}"
+                );
+                assert!(!target_dir.join("A").exists());
+            } else {
+                res.unwrap();
+                assert_eq!(fs::read(target_dir.join("B")).unwrap(), b"top secret");
+            }
+        }
+
+        fs::remove_dir_all(&target_dir).unwrap();
+        for path in [key_path, path, config_path] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
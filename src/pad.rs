@@ -0,0 +1,169 @@
+// Length-hiding padding: the plaintext is followed by `0x80` and as many zeros as needed to reach
+// the next Padmé length (or a minimum), so the ciphertext only reveals a coarse size class. The
+// padding is added at the end of the input, so the length doesn't have to be known in advance.
+
+use std::io::{self, Read, Write};
+
+use color_eyre::eyre::{self, bail};
+
+const MARKER: u8 = 0x80;
+// Padmé barely pads short data, but lengths of e.g. passwords are the ones worth hiding
+const MIN_PADDED_LEN: u64 = 256;
+
+// Padmé: the padded length leaks O(log log L) bits, with at most 12% overhead
+fn padme(len: u64) -> u64 {
+    if len < 2 {
+        return len;
+    }
+    let exponent = len.ilog2();
+    let exponent_bits = exponent.ilog2() + 1;
+    let mask = (1_u64 << (exponent - exponent_bits)) - 1;
+    (len + mask) & !mask
+}
+
+// Length of `len` bytes of data with the padding, which is at least the marker
+fn padded_len(len: u64) -> u64 {
+    padme(len + 1).max(MIN_PADDED_LEN)
+}
+
+pub struct Padded<R> {
+    inner: R,
+    len: u64,
+    // bytes of padding written so far and in total, once the input is exhausted
+    padding: Option<(u64, u64)>,
+}
+
+impl<R> Padded<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            len: 0,
+            padding: None,
+        }
+    }
+}
+
+impl<R: Read> Read for Padded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let (written, total) = if let Some(padding) = &mut self.padding {
+            padding
+        } else {
+            let read = self.inner.read(buf)?;
+            if read > 0 {
+                self.len += read as u64;
+                return Ok(read);
+            }
+            self.padding.insert((0, padded_len(self.len) - self.len))
+        };
+        let len = usize::try_from(*total - *written).map_or(buf.len(), |left| left.min(buf.len()));
+        buf[..len].fill(0);
+        if *written == 0 && len > 0 {
+            buf[0] = MARKER;
+        }
+        *written += len as u64;
+        Ok(len)
+    }
+}
+
+enum State {
+    // the file isn't padded
+    Disabled,
+    // the last non-zero byte (if any) wasn't the marker, so everything is written through
+    Data,
+    // the marker followed by this many zeros is held back, it may turn out to be the padding
+    Padding(u64),
+}
+
+// Writer that strips the padding from what's written to it
+pub struct Unpad<W> {
+    inner: W,
+    state: State,
+}
+
+impl<W: Write> Unpad<W> {
+    pub fn new(inner: W, padded: bool) -> Self {
+        Self {
+            inner,
+            state: if padded { State::Data } else { State::Disabled },
+        }
+    }
+
+    // Fails if the data doesn't end with padding
+    pub fn finish(self) -> eyre::Result<W> {
+        match self.state {
+            State::Data => bail!("padding is malformed"),
+            State::Disabled | State::Padding(_) => Ok(self.inner),
+        }
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        if let State::Padding(zeros) = self.state {
+            self.inner.write_all(&[MARKER])?;
+            io::copy(&mut io::repeat(0).take(zeros), &mut self.inner)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Unpad<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let State::Disabled = self.state {
+            return self.inner.write(buf);
+        }
+        let Some(last) = buf.iter().rposition(|&byte| byte != 0) else {
+            match &mut self.state {
+                State::Padding(zeros) => *zeros += buf.len() as u64,
+                _ => self.inner.write_all(buf)?,
+            }
+            return Ok(buf.len());
+        };
+        // nothing written before `last` can be the padding anymore
+        self.release()?;
+        if buf[last] == MARKER {
+            self.inner.write_all(&buf[..last])?;
+            self.state = State::Padding((buf.len() - last - 1) as u64);
+        } else {
+            self.inner.write_all(buf)?;
+            self.state = State::Data;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::io::{Read as _, Write as _};
+
+    use proptest::{collection::vec, prelude::Just, prop_assert_eq, prop_oneof, proptest};
+
+    use super::{padded_len, Padded, Unpad};
+
+    proptest! {
+        #[test]
+        fn roundtrip(
+            // mostly zeros and markers, to make the data look like padding
+            input in vec(prop_oneof![Just(0), Just(0x80), 0..=u8::MAX], 0..2048),
+            split in 1..64_usize,
+        ) {
+            let mut padded = Vec::new();
+            Padded::new(input.as_slice()).read_to_end(&mut padded).unwrap();
+            prop_assert_eq!(padded.len() as u64, padded_len(input.len() as u64));
+
+            let mut unpadded = Vec::new();
+            let mut unpad = Unpad::new(&mut unpadded, true);
+            for chunk in padded.chunks(split) {
+                unpad.write_all(chunk).unwrap();
+            }
+            unpad.finish().unwrap();
+            prop_assert_eq!(unpadded, input);
+        }
+    }
+}
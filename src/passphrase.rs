@@ -0,0 +1,237 @@
+// Passphrase-protected key files: the contents of a regular key file, encrypted with a key
+// derived from the passphrase with Argon2id. The KDF parameters are stored in the file.
+
+use std::os::unix::io::RawFd;
+
+use chacha20poly1305::{
+    aead::{Aead as _, Key, Nonce, Payload},
+    AeadCore as _, KeyInit as _, XChaCha20Poly1305 as Cipher,
+};
+use color_eyre::eyre::{self, bail, ensure, eyre, WrapErr as _};
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize as _, Zeroizing};
+
+use crate::keysource::KeySource;
+
+const BEGIN: &str = "-----BEGIN CLASSIFIED PROTECTED KEY-----";
+const END: &str = "-----END CLASSIFIED PROTECTED KEY-----";
+const AAD: &[u8] = b"classified protected key";
+const MAX_PASSPHRASE_LEN: usize = 64 * 1024;
+
+const M_COST: u32 = 64 * 1024;
+const T_COST: u32 = 3;
+const P_COST: u32 = 1;
+// don't let a crafted key file exhaust memory (1 GiB) or take forever
+const MAX_M_COST: u32 = 1024 * 1024;
+const MAX_T_COST: u32 = 64;
+
+enum Source {
+    Prompt,
+    Fd(RawFd),
+    Unavailable(&'static str),
+}
+
+// Where to get passphrases from
+pub struct Passphrase {
+    source: Source,
+    // a file descriptor can be read only once, so its passphrase is used for every key
+    cached: Option<Zeroizing<String>>,
+}
+
+impl Passphrase {
+    // Reads from `fd` if it's given, otherwise prompts on the terminal
+    pub fn new(fd: Option<RawFd>) -> Self {
+        Self {
+            source: fd.map_or(Source::Prompt, Source::Fd),
+            cached: None,
+        }
+    }
+
+    pub fn unavailable(reason: &'static str) -> Self {
+        Self {
+            source: Source::Unavailable(reason),
+            cached: None,
+        }
+    }
+
+    pub fn get(&mut self, prompt: &str) -> eyre::Result<Zeroizing<String>> {
+        match self.source {
+            Source::Prompt => rpassword::prompt_password(prompt)
+                .map(Zeroizing::new)
+                .wrap_err("failed to read passphrase"),
+            Source::Fd(fd) => {
+                if self.cached.is_none() {
+                    self.cached = Some(read_fd(fd)?);
+                }
+                Ok(self.cached.clone().expect("passphrase was just read"))
+            }
+            Source::Unavailable(reason) => bail!("{reason}"),
+        }
+    }
+
+    // Asks twice when prompting, to catch typos
+    pub fn get_new(&mut self) -> eyre::Result<Zeroizing<String>> {
+        let passphrase = self.get("New passphrase: ")?;
+        if let Source::Prompt = self.source {
+            ensure!(
+                *self.get("Repeat passphrase: ")? == *passphrase,
+                "passphrases don't match"
+            );
+        }
+        ensure!(!passphrase.is_empty(), "passphrase is empty");
+        Ok(passphrase)
+    }
+}
+
+fn read_fd(fd: RawFd) -> eyre::Result<Zeroizing<String>> {
+    let mut buf = Zeroizing::new(vec![0_u8; MAX_PASSPHRASE_LEN + 1]);
+    let mut len = 0;
+    while len < buf.len() {
+        match nix::unistd::read(fd, &mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(nix::errno::Errno::EINTR) => {}
+            Err(err) => return Err(err).wrap_err("failed to read passphrase"),
+        }
+    }
+    ensure!(len <= MAX_PASSPHRASE_LEN, "passphrase is too long");
+    let passphrase = std::str::from_utf8(&buf[..len]).wrap_err("passphrase is not valid UTF-8")?;
+    let passphrase = passphrase.strip_suffix('\n').unwrap_or(passphrase);
+    let passphrase = passphrase.strip_suffix('\r').unwrap_or(passphrase);
+    Ok(Zeroizing::new(passphrase.to_owned()))
+}
+
+#[derive(Deserialize, Serialize)]
+struct Protected {
+    // Argon2id parameters
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    #[serde(with = "serde_bytes")]
+    salt: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    nonce: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    wrapped: Vec<u8>,
+}
+
+impl Protected {
+    fn cipher(&self, passphrase: &str) -> eyre::Result<Cipher> {
+        ensure!(
+            self.m_cost <= MAX_M_COST && self.t_cost <= MAX_T_COST,
+            "key derivation parameters are too expensive"
+        );
+        let params = argon2::Params::new(self.m_cost, self.t_cost, self.p_cost, None)
+            .map_err(|err| eyre!("invalid key derivation parameters: {err}"))?;
+        let mut key = Key::<Cipher>::default();
+        argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &self.salt, &mut key)
+            .map_err(|err| eyre!("failed to derive key from passphrase: {err}"))?;
+        let cipher = Cipher::new(&key);
+        key.zeroize();
+        Ok(cipher)
+    }
+}
+
+pub fn is_protected(contents: &[u8]) -> bool {
+    contents.trim_ascii_start().starts_with(BEGIN.as_bytes())
+}
+
+pub fn protect(
+    contents: &[u8],
+    passphrase: &str,
+    mut rng: impl RngCore + CryptoRng,
+) -> eyre::Result<String> {
+    let mut salt = vec![0; 16];
+    rng.fill_bytes(&mut salt);
+    let nonce = Cipher::generate_nonce(&mut rng);
+    let mut protected = Protected {
+        m_cost: M_COST,
+        t_cost: T_COST,
+        p_cost: P_COST,
+        salt,
+        nonce: nonce.to_vec(),
+        wrapped: Vec::new(),
+    };
+    protected.wrapped = protected
+        .cipher(passphrase)?
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: contents,
+                aad: AAD,
+            },
+        )
+        .map_err(|_| eyre!("failed to encrypt key"))?;
+
+    let encoded = base64::encode(serde_cbor::to_vec(&protected)?);
+    let mut armored = format!("{BEGIN}\n");
+    for line in encoded.as_bytes().chunks(64) {
+        armored.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        armored.push('\n');
+    }
+    armored.push_str(END);
+    armored.push('\n');
+    Ok(armored)
+}
+
+pub fn unprotect(contents: &[u8], passphrase: &str) -> eyre::Result<Zeroizing<Vec<u8>>> {
+    let encoded: String = std::str::from_utf8(contents)
+        .wrap_err("malformed protected key")?
+        .trim()
+        .strip_prefix(BEGIN)
+        .and_then(|rest| rest.strip_suffix(END))
+        .ok_or_else(|| eyre!("malformed protected key"))?
+        .split_whitespace()
+        .collect();
+    let protected: Protected =
+        serde_cbor::from_slice(&base64::decode(encoded).wrap_err("failed to decode key")?)
+            .wrap_err("failed to parse protected key")?;
+    ensure!(
+        protected.nonce.len() == Nonce::<Cipher>::default().len(),
+        "wrong nonce length"
+    );
+    protected
+        .cipher(passphrase)?
+        .decrypt(
+            Nonce::<Cipher>::from_slice(&protected.nonce),
+            Payload {
+                msg: &protected.wrapped,
+                aad: AAD,
+            },
+        )
+        .map(Zeroizing::new)
+        .map_err(|_| eyre!("wrong passphrase"))
+}
+
+// Contents of a key file, with the passphrase protection removed
+pub fn read_key_file(
+    source: &KeySource,
+    passphrase: &mut Passphrase,
+) -> eyre::Result<Zeroizing<Vec<u8>>> {
+    let contents = source.read()?;
+    if !is_protected(&contents) {
+        return Ok(contents);
+    }
+    let passphrase = passphrase.get(&format!("Passphrase for {source}: "))?;
+    unprotect(&contents, &passphrase).wrap_err_with(|| format!("failed to unlock {source}"))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{protect, unprotect};
+
+    #[test]
+    fn roundtrip() {
+        let contents = b"first second third\n";
+        let protected = protect(contents, "hunter2", rand::thread_rng()).unwrap();
+        assert!(!protected.contains("first"));
+        assert_eq!(
+            *unprotect(protected.as_bytes(), "hunter2").unwrap(),
+            contents
+        );
+        assert!(unprotect(protected.as_bytes(), "hunter3").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+// Shamir's secret sharing of keys over GF(2^8), for backups that don't have a single point of
+// compromise: every byte of the key is the constant term of a random polynomial of degree
+// `threshold - 1`, and share `x` holds the values of the polynomials at `x`. Shares are armored
+// with `keyarmor::Words`, so each has its own checksum, and carry the `KeyId` of the key, so the
+// combined key can be checked and corrupt shares found.
+
+use std::{fmt, str::FromStr};
+
+use color_eyre::eyre::{self, bail, ensure, eyre, WrapErr as _};
+use itertools::Itertools as _;
+use rand::{CryptoRng, RngCore};
+use zeroize::{Zeroize as _, Zeroizing};
+
+use crate::{
+    key::{ArmoredKey, KeyId},
+    keyarmor,
+};
+
+// Multiplication in GF(2^8) with the AES polynomial, without data-dependent branches
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & 0_u8.wrapping_sub(b & 1);
+        a = (a << 1) ^ (0x1b & 0_u8.wrapping_sub(a >> 7));
+        b >>= 1;
+    }
+    product
+}
+
+// a^254, which is the inverse of a for every a except 0
+fn inverse(a: u8) -> u8 {
+    let mut result = 1;
+    let mut power = a;
+    for bit in 0..8 {
+        if 254 >> bit & 1 == 1 {
+            result = mul(result, power);
+        }
+        power = mul(power, power);
+    }
+    result
+}
+
+// Values at `x` of the polynomials going through the values of `shares`
+fn interpolate(shares: &[&Share], x: u8) -> [u8; 32] {
+    let mut result = [0_u8; 32];
+    for share in shares {
+        let mut basis = 1;
+        for other in shares {
+            if other.index != share.index {
+                basis = mul(
+                    basis,
+                    mul(x ^ other.index, inverse(share.index ^ other.index)),
+                );
+            }
+        }
+        for (result, value) in result.iter_mut().zip(share.value) {
+            *result ^= mul(basis, value);
+        }
+    }
+    result
+}
+
+pub struct Share {
+    key_id: KeyId,
+    index: u8,
+    threshold: u8,
+    value: [u8; 32],
+}
+
+impl fmt::Display for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "key {}, share {}, threshold {}: {}",
+            self.key_id,
+            self.index,
+            self.threshold,
+            keyarmor::Words::new(
+                &self.value,
+                keyarmor::Checksum::Crc8,
+                keyarmor::Wordlist::English
+            )
+            .expect("shares are as long as keys")
+        )
+    }
+}
+
+fn field<'a>(field: Option<&'a str>, name: &str) -> eyre::Result<&'a str> {
+    field
+        .and_then(|field| field.trim().strip_prefix(name))
+        .and_then(|value| value.strip_prefix(' '))
+        .ok_or_else(|| {
+            eyre!("share should start with \"key <key id>, share <number>, threshold <number>:\"")
+        })
+}
+
+impl FromStr for Share {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        let (header, words) = s.split_once(':').unwrap_or(("", s));
+        let mut fields = header.split(',');
+        let key_id = field(fields.next(), "key")?.parse()?;
+        let index = field(fields.next(), "share")?
+            .parse()
+            .wrap_err("wrong share number")?;
+        let threshold = field(fields.next(), "threshold")?
+            .parse()
+            .wrap_err("wrong share threshold")?;
+        ensure!(fields.next().is_none(), "unexpected field in share header");
+        ensure!(index > 0, "share number can't be 0");
+        ensure!(threshold > 1, "share threshold should be at least 2");
+
+        let words: Vec<_> = words.split_whitespace().collect();
+        let words = keyarmor::Words::from_key_words(&words)
+            .wrap_err_with(|| format!("share {index} is corrupt"))?;
+        let mut value = [0_u8; 32];
+        value.copy_from_slice(words.bytes());
+        Ok(Self {
+            key_id,
+            index,
+            threshold,
+            value,
+        })
+    }
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+pub fn split(
+    key: &ArmoredKey,
+    threshold: u8,
+    shares: u8,
+    mut rng: impl RngCore + CryptoRng,
+) -> eyre::Result<Vec<Share>> {
+    ensure!(
+        key.imported_secret().is_none(),
+        "keys imported from age or SSH can't be split"
+    );
+    ensure!(threshold > 1, "threshold should be at least 2");
+    ensure!(
+        threshold <= shares,
+        "threshold can't be larger than the number of shares"
+    );
+    let mut coefficients = Zeroizing::new(vec![[0_u8; 32]; usize::from(threshold) - 1]);
+    for coefficient in coefficients.iter_mut() {
+        rng.fill_bytes(coefficient);
+    }
+    Ok((1..=shares)
+        .map(|index| {
+            let mut value = [0_u8; 32];
+            for (byte, value) in value.iter_mut().enumerate() {
+                for coefficient in coefficients.iter().rev() {
+                    *value = mul(*value, index) ^ coefficient[byte];
+                }
+                *value = mul(*value, index) ^ key[byte];
+            }
+            Share {
+                key_id: key.id(),
+                index,
+                threshold,
+                value,
+            }
+        })
+        .collect())
+}
+
+/// Restore the key from at least `threshold` shares, also returning the numbers of the shares
+/// that didn't fit
+pub fn combine(shares: &[Share]) -> eyre::Result<(ArmoredKey, Vec<u8>)> {
+    let first = shares.first().ok_or_else(|| eyre!("no shares given"))?;
+    for share in shares {
+        ensure!(
+            share.key_id == first.key_id && share.threshold == first.threshold,
+            "shares of different splits can't be combined: key {} with threshold {} and key {} \
+             with threshold {}",
+            first.key_id,
+            first.threshold,
+            share.key_id,
+            share.threshold,
+        );
+    }
+    let threshold = usize::from(first.threshold);
+    let distinct = shares.iter().map(|share| share.index).unique().count();
+    ensure!(
+        distinct >= threshold,
+        "{threshold} different shares are needed, but only {distinct} were given"
+    );
+
+    // a corrupt share (or one with a wrong number) gives a different key, so try to do without
+    for subset in shares.iter().combinations(threshold) {
+        if subset.iter().map(|share| share.index).unique().count() < threshold {
+            continue;
+        }
+        let mut secret = interpolate(&subset, 0);
+        let key = ArmoredKey::new(secret.into());
+        secret.zeroize();
+        if key.id() != first.key_id {
+            continue;
+        }
+        let corrupt = shares
+            .iter()
+            .filter(|share| {
+                let mut value = interpolate(&subset, share.index);
+                let fits = value == share.value;
+                value.zeroize();
+                !fits
+            })
+            .map(|share| share.index)
+            .collect();
+        return Ok((key, corrupt));
+    }
+    if shares.len() > threshold {
+        bail!(
+            "shares don't combine to key {}: too many of them are corrupt",
+            first.key_id
+        );
+    }
+    bail!(
+        "shares don't combine to key {}: one of them is corrupt, more shares are needed to find \
+         out which",
+        first.key_id
+    );
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use chacha20poly1305::{KeyInit as _, XChaCha20Poly1305 as Cipher};
+    use proptest::{
+        prelude::{Just, Strategy as _},
+        prop_assert_eq, proptest,
+        sample::subsequence,
+    };
+
+    use super::{combine, split, Share};
+    use crate::key::ArmoredKey;
+
+    proptest! {
+        #[test]
+        fn roundtrip(
+            (threshold, picked) in (2..=5_u8).prop_flat_map(|threshold| {
+                (Just(threshold), subsequence((0..7).collect::<Vec<usize>>(), usize::from(threshold)..=7))
+            }),
+            corrupt in 0..7_usize,
+        ) {
+            let key = ArmoredKey::new(Cipher::generate_key(rand::thread_rng()));
+            let shares = split(&key, threshold, 7, rand::thread_rng()).unwrap();
+            let mut picked: Vec<Share> = picked
+                .iter()
+                .map(|&index| shares[index].to_string().parse().unwrap())
+                .collect();
+            let (combined, corrupted) = combine(&picked).unwrap();
+            prop_assert_eq!(*combined, *key);
+            prop_assert_eq!(corrupted, Vec::<u8>::new());
+
+            // one corrupt share can be singled out if there are enough others
+            if picked.len() > usize::from(threshold) {
+                let corrupt = corrupt % picked.len();
+                picked[corrupt].value[0] ^= 1;
+                let (combined, corrupted) = combine(&picked).unwrap();
+                prop_assert_eq!(*combined, *key);
+                prop_assert_eq!(corrupted, vec![picked[corrupt].index]);
+            }
+        }
+    }
+}
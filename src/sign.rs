@@ -0,0 +1,287 @@
+// Signatures proving who encrypted a file: the AEAD tag only shows that the ciphertext was made by
+// someone holding the symmetric key, which every recipient does. Signed files (`FLAG_SIGNED`) carry
+// the Ed25519 public key of the signer in the body and end with a signature over a hash of
+// everything before it (header, body and payload), appended after the payload. Every `ArmoredKey`
+// has a signing key derived from it.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+use color_eyre::eyre::{self, bail, eyre, WrapErr as _};
+use ed25519_dalek::{Signature, Signer as _, SigningKey, VerifyingKey, SIGNATURE_LENGTH};
+use hkdf::Hkdf;
+use serde::Deserialize;
+use sha2::{Digest as _, Sha256, Sha512};
+use zeroize::Zeroize as _;
+
+use crate::{
+    envelope::{Envelope, FLAG_SIGNED},
+    key::ArmoredKey,
+};
+
+const PREFIX: &str = "ed25519:";
+const READ_LEN: usize = 8192;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PublicKey(VerifyingKey);
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{PREFIX}{}",
+            base64::encode_config(self.0.as_bytes(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        let encoded = s
+            .strip_prefix(PREFIX)
+            .ok_or_else(|| eyre!("signing public key should start with {PREFIX:?}"))?;
+        let bytes = base64::decode_config(encoded, base64::URL_SAFE_NO_PAD)
+            .wrap_err("failed to decode signing public key")?;
+        Self::from_slice(&bytes)
+    }
+}
+
+impl TryFrom<String> for PublicKey {
+    type Error = eyre::Report;
+
+    fn try_from(s: String) -> eyre::Result<Self> {
+        s.parse()
+    }
+}
+
+impl PublicKey {
+    fn from_slice(bytes: &[u8]) -> eyre::Result<Self> {
+        let bytes = <[u8; 32]>::try_from(bytes).map_err(|_| {
+            eyre!(
+                "wrong signing public key length: {} instead of 32",
+                bytes.len()
+            )
+        })?;
+        VerifyingKey::from_bytes(&bytes)
+            .map(Self)
+            .map_err(|_| eyre!("signing public key is not a valid Ed25519 point"))
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        self.0.to_bytes().to_vec()
+    }
+}
+
+pub fn signing_key(key: &ArmoredKey) -> SigningKey {
+    let mut seed = [0_u8; 32];
+    Hkdf::<Sha256>::new(None, key)
+        .expand(b"classified ed25519 signing key", &mut seed)
+        .expect("32 bytes is a valid HKDF output length");
+    let signing_key = SigningKey::from_bytes(&seed);
+    seed.zeroize();
+    signing_key
+}
+
+pub fn public(key: &SigningKey) -> PublicKey {
+    PublicKey(key.verifying_key())
+}
+
+// What is actually signed, so the signature can't be mistaken for one made for something else
+fn message(hasher: Sha512) -> Vec<u8> {
+    let mut message = b"classified signature".to_vec();
+    message.extend_from_slice(&hasher.finalize());
+    message
+}
+
+// Writer that appends a signature over everything written to it on `finish`, if it has a key
+pub struct Signer<'a, W> {
+    inner: W,
+    key: Option<(&'a SigningKey, Sha512)>,
+}
+
+impl<'a, W: Write> Signer<'a, W> {
+    pub fn new(inner: W, key: Option<&'a SigningKey>) -> Self {
+        Self {
+            inner,
+            key: key.map(|key| (key, Sha512::new())),
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some((key, hasher)) = self.key {
+            self.inner
+                .write_all(&key.sign(&message(hasher)).to_bytes())?;
+        }
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for Signer<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some((_, hasher)) = &mut self.key {
+            hasher.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct Signed {
+    signer: PublicKey,
+    hasher: Sha512,
+    // the last `SIGNATURE_LENGTH` bytes read may turn out to be the signature
+    held: Vec<u8>,
+    verified: bool,
+}
+
+impl Signed {
+    fn verify(&mut self) -> io::Result<()> {
+        let signature = <[u8; SIGNATURE_LENGTH]>::try_from(self.held.as_slice())
+            .map_err(|_| io::Error::new(io::ErrorKind::UnexpectedEof, "signature is truncated"))?;
+        self.held.clear();
+        self.signer
+            .0
+            .verify_strict(
+                &message(self.hasher.clone()),
+                &Signature::from_bytes(&signature),
+            )
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "signature is invalid"))?;
+        self.verified = true;
+        Ok(())
+    }
+}
+
+// Reader for the payload of a file that strips and verifies its signature. The signature is
+// checked when the end of the payload is reached, so reading fails before the last of the data
+// is returned if it doesn't match.
+pub struct Verifier<R> {
+    inner: R,
+    // `None` if the file isn't signed, then everything is read through
+    signed: Option<Signed>,
+}
+
+impl<R> Verifier<R> {
+    pub fn new(inner: R, envelope: &Envelope) -> eyre::Result<Self> {
+        let signed = match (
+            envelope.header.flags & FLAG_SIGNED != 0,
+            &envelope.body.signer,
+        ) {
+            (false, None) => None,
+            (true, Some(signer)) => {
+                let mut hasher = Sha512::new();
+                hasher.update(&envelope.associated_data);
+                Some(Signed {
+                    signer: PublicKey::from_slice(signer)?,
+                    hasher,
+                    held: Vec::new(),
+                    verified: false,
+                })
+            }
+            (true, None) => bail!("file is marked as signed, but has no signer"),
+            (false, Some(_)) => bail!("file has a signer, but isn't marked as signed"),
+        };
+        Ok(Self { inner, signed })
+    }
+
+    pub fn signer(&self) -> Option<PublicKey> {
+        self.signed.as_ref().map(|signed| signed.signer)
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    // Fails if the file is signed, but the signature wasn't reached
+    pub fn finish(self) -> eyre::Result<()> {
+        match self.signed {
+            Some(signed) if !signed.verified => bail!("signature wasn't checked"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<R: Read> Read for Verifier<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(signed) = &mut self.signed else {
+            return self.inner.read(buf);
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if signed.held.len() > SIGNATURE_LENGTH {
+                let len = (signed.held.len() - SIGNATURE_LENGTH).min(buf.len());
+                buf[..len].copy_from_slice(&signed.held[..len]);
+                signed.hasher.update(&buf[..len]);
+                signed.held.drain(..len);
+                return Ok(len);
+            }
+            if signed.verified {
+                return Ok(0);
+            }
+            let start = signed.held.len();
+            signed.held.resize(start + READ_LEN, 0);
+            let read = self.inner.read(&mut signed.held[start..]);
+            signed
+                .held
+                .truncate(start + read.as_ref().map_or(0, |read| *read));
+            if read? == 0 {
+                signed.verify()?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::io::{Read as _, Write as _};
+
+    use chacha20poly1305::{KeyInit as _, XChaCha20Poly1305 as Cipher};
+    use proptest::{collection::vec, prop_assert, prop_assert_eq, proptest};
+
+    use super::{public, signing_key, PublicKey, Signer, Verifier};
+    use crate::{
+        envelope::{Algorithm, Body, Envelope, Header, FLAG_SIGNED},
+        key::ArmoredKey,
+    };
+
+    proptest! {
+        #[test]
+        fn roundtrip(payload in vec(0..=u8::MAX, 0..20000), flip in 0..20064_usize) {
+            let key = signing_key(&ArmoredKey::new(Cipher::generate_key(rand::thread_rng())));
+            let public_key: PublicKey = public(&key).to_string().parse().unwrap();
+            let header = Header { flags: FLAG_SIGNED, ..Header::new(Algorithm::XChaCha20Poly1305) };
+            let body = Body { signer: Some(public_key.to_bytes()), ..Body::default() };
+            let envelope = Envelope::new(header, body).unwrap();
+
+            let mut signer = Signer::new(Vec::new(), Some(&key));
+            signer.write_all(&envelope.associated_data).unwrap();
+            signer.write_all(&payload).unwrap();
+            let signed = signer.finish().unwrap();
+            let mut signed = signed[envelope.associated_data.len()..].to_vec();
+
+            let mut verifier = Verifier::new(signed.as_slice(), &envelope).unwrap();
+            prop_assert_eq!(verifier.signer(), Some(public_key));
+            let mut read = Vec::new();
+            verifier.read_to_end(&mut read).unwrap();
+            verifier.finish().unwrap();
+            prop_assert_eq!(read, payload);
+
+            let flip = flip % signed.len();
+            signed[flip] ^= 1;
+            let mut verifier = Verifier::new(signed.as_slice(), &envelope).unwrap();
+            prop_assert!(verifier.read_to_end(&mut Vec::new()).is_err());
+        }
+    }
+}
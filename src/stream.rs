@@ -0,0 +1,214 @@
+// STREAM construction: every chunk is sealed separately with the nonce
+// `prefix || counter (4 bytes, BE) || last chunk flag (1 byte)`, so chunks can't be reordered,
+// dropped or truncated without detection.
+
+use std::io::{self, Read, Write};
+
+use chacha20poly1305::aead::{AeadCore, AeadInPlace, Nonce};
+use color_eyre::eyre::{self, ensure, eyre, WrapErr as _};
+use generic_array::typenum::Unsigned as _;
+use rand::{CryptoRng, RngCore};
+use zeroize::{Zeroize as _, Zeroizing};
+
+use crate::envelope::Envelope;
+
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+// the rest of the nonce is the counter and the flag
+fn prefix_len<C: AeadCore>() -> usize {
+    C::NonceSize::USIZE - 5
+}
+
+pub fn generate_prefix<C: AeadCore>(mut rng: impl RngCore + CryptoRng) -> Vec<u8> {
+    let mut prefix = vec![0; prefix_len::<C>()];
+    rng.fill_bytes(&mut prefix);
+    prefix
+}
+
+fn nonce<C: AeadCore>(prefix: &[u8], counter: u32, last: bool) -> Nonce<C> {
+    let len = prefix_len::<C>();
+    let mut nonce = Nonce::<C>::default();
+    nonce[..len].copy_from_slice(prefix);
+    nonce[len..len + 4].copy_from_slice(&counter.to_be_bytes());
+    nonce[len + 4] = last.into();
+    nonce
+}
+
+// Like `read_exact`, but stops at EOF and returns the number of bytes read
+fn read_full(reader: &mut impl Read, mut buf: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while !buf.is_empty() {
+        match reader.read(buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                total += n;
+                buf = &mut buf[n..];
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(total)
+}
+
+fn params<C: AeadCore>(envelope: &Envelope) -> eyre::Result<(&[u8], usize)> {
+    let prefix = envelope.body.nonce.as_slice();
+    ensure!(
+        prefix.len() == prefix_len::<C>(),
+        "wrong nonce prefix length"
+    );
+    let chunk_size = envelope
+        .body
+        .chunk_size
+        .ok_or_else(|| eyre!("chunk size is not specified"))?;
+    ensure!(
+        (1..=MAX_CHUNK_SIZE).contains(&chunk_size),
+        "unsupported chunk size {chunk_size}"
+    );
+    Ok((prefix, chunk_size as usize))
+}
+
+// Fills `buf`, starting with the byte carried over from the previous call, and checks whether
+// the input ends right after it. The byte read ahead is stored in `carry`.
+pub fn fill_chunk(
+    input: &mut impl Read,
+    buf: &mut [u8],
+    carry: &mut Option<u8>,
+) -> io::Result<(usize, bool)> {
+    let carried = if let Some(byte) = carry.take() {
+        buf[0] = byte;
+        1
+    } else {
+        0
+    };
+    let filled = carried + read_full(input, &mut buf[carried..])?;
+    if filled < buf.len() {
+        return Ok((filled, true));
+    }
+
+    let mut next = [0_u8];
+    if read_full(input, &mut next)? == 0 {
+        Ok((filled, true))
+    } else {
+        *carry = Some(next[0]);
+        Ok((filled, false))
+    }
+}
+
+pub fn encrypt<C: AeadInPlace>(
+    cipher: &C,
+    envelope: &Envelope,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> eyre::Result<()> {
+    let (prefix, chunk_size) = params::<C>(envelope)?;
+    let tag_len = C::TagSize::USIZE;
+    let aad = envelope.associated_data.as_slice();
+    let mut buf = Zeroizing::new(Vec::with_capacity(chunk_size + tag_len));
+    let mut carry = None;
+
+    for counter in 0_u32.. {
+        buf.resize(chunk_size, 0);
+        let (filled, last) =
+            fill_chunk(&mut input, &mut buf, &mut carry).wrap_err("failed to read input")?;
+        buf.truncate(filled);
+        cipher
+            .encrypt_in_place(&nonce::<C>(prefix, counter, last), aad, &mut *buf)
+            .map_err(|_| eyre!("failed to encrypt"))?;
+        output.write_all(&buf)?;
+        if last {
+            return Ok(());
+        }
+    }
+
+    Err(eyre!("input is too long"))
+}
+
+pub fn decrypt<C: AeadInPlace>(
+    cipher: &C,
+    envelope: &Envelope,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> eyre::Result<()> {
+    let (prefix, chunk_size) = params::<C>(envelope)?;
+    let tag_len = C::TagSize::USIZE;
+    let aad = envelope.associated_data.as_slice();
+    let mut buf = Zeroizing::new(Vec::with_capacity(chunk_size + tag_len));
+    let mut carry = None;
+
+    for counter in 0_u32.. {
+        buf.resize(chunk_size + tag_len, 0);
+        let (filled, last) = fill_chunk(&mut input, &mut buf, &mut carry)
+            .wrap_err("failed to read encrypted file")?;
+        ensure!(filled >= tag_len, "encrypted file is truncated");
+        buf.truncate(filled);
+        cipher
+            .decrypt_in_place(&nonce::<C>(prefix, counter, last), aad, &mut *buf)
+            .map_err(|_| eyre!("failed to decrypt chunk {counter}"))?;
+        output
+            .write_all(&buf)
+            .wrap_err("failed to write decrypted data")?;
+        buf.zeroize();
+        if last {
+            return Ok(());
+        }
+    }
+
+    Err(eyre!("encrypted file is too long"))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use aes_gcm_siv::Aes256GcmSiv;
+    use chacha20poly1305::{aead::AeadInPlace, KeyInit, XChaCha20Poly1305};
+    use proptest::{
+        collection::vec, prop_assert, prop_assert_eq, proptest, test_runner::TestCaseResult,
+    };
+
+    use super::{decrypt, encrypt, generate_prefix};
+    use crate::envelope::{Algorithm, Body, Envelope, Header, FLAG_STREAM};
+
+    fn check<C: AeadInPlace + KeyInit>(
+        algorithm: Algorithm,
+        input: &[u8],
+        chunk_size: u32,
+    ) -> TestCaseResult {
+        let cipher = C::new(&C::generate_key(rand::thread_rng()));
+        let envelope = Envelope::new(
+            Header {
+                flags: FLAG_STREAM,
+                ..Header::new(algorithm)
+            },
+            Body {
+                nonce: generate_prefix::<C>(rand::thread_rng()),
+                chunk_size: Some(chunk_size),
+                ..Body::default()
+            },
+        )
+        .unwrap();
+
+        let mut encrypted = Vec::new();
+        encrypt(&cipher, &envelope, input, &mut encrypted).unwrap();
+        let mut decrypted = Vec::new();
+        decrypt(&cipher, &envelope, encrypted.as_slice(), &mut decrypted).unwrap();
+        prop_assert_eq!(&decrypted, &input);
+
+        // dropping the last chunk must be detected
+        let tail = encrypted.len() - ((input.len() % chunk_size as usize) + 16);
+        if tail > 0 {
+            let mut sink = Vec::new();
+            prop_assert!(decrypt(&cipher, &envelope, &encrypted[..tail], &mut sink).is_err());
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip(input in vec(0..=u8::MAX, 0..1024), chunk_size in 1..300_u32) {
+            check::<XChaCha20Poly1305>(Algorithm::XChaCha20Poly1305, &input, chunk_size)?;
+            check::<Aes256GcmSiv>(Algorithm::Aes256GcmSiv, &input, chunk_size)?;
+        }
+    }
+}
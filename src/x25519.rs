@@ -0,0 +1,163 @@
+// Public-key recipients: the data key is wrapped with a key derived from an ephemeral X25519
+// exchange, so encrypting doesn't require the secret key. Every `ArmoredKey` has an X25519
+// identity derived from it, unless it was imported from another tool.
+
+use std::{fmt, str::FromStr};
+
+use chacha20poly1305::{
+    aead::{Aead as _, Key, Nonce},
+    KeyInit as _, XChaCha20Poly1305 as Cipher,
+};
+use color_eyre::eyre::{self, ensure, eyre, WrapErr as _};
+use hkdf::Hkdf;
+use rand::{CryptoRng, RngCore};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, StaticSecret};
+use zeroize::Zeroize as _;
+
+use crate::{age, envelope::Recipient, key::ArmoredKey};
+
+const PREFIX: &str = "x25519:";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey(pub x25519_dalek::PublicKey);
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{PREFIX}{}",
+            base64::encode_config(self.0.as_bytes(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        if s.starts_with("age1") {
+            return age::parse_recipient(s).map(Self);
+        }
+        let encoded = s
+            .strip_prefix(PREFIX)
+            .ok_or_else(|| eyre!("public key should start with {PREFIX:?}"))?;
+        let bytes = base64::decode_config(encoded, base64::URL_SAFE_NO_PAD)
+            .wrap_err("failed to decode public key")?;
+        let bytes = <[u8; 32]>::try_from(bytes)
+            .map_err(|v| eyre!("wrong public key length: {} instead of 32", v.len()))?;
+        Ok(Self(bytes.into()))
+    }
+}
+
+pub fn secret(key: &ArmoredKey) -> StaticSecret {
+    if let Some(secret) = key.imported_secret() {
+        return secret.clone();
+    }
+    let mut bytes = [0_u8; 32];
+    Hkdf::<Sha256>::new(None, key)
+        .expand(b"classified x25519 identity", &mut bytes)
+        .expect("32 bytes is a valid HKDF output length");
+    let secret = StaticSecret::from(bytes);
+    bytes.zeroize();
+    secret
+}
+
+pub fn public(key: &ArmoredKey) -> PublicKey {
+    PublicKey((&secret(key)).into())
+}
+
+fn wrapping_cipher(
+    shared: &x25519_dalek::SharedSecret,
+    ephemeral: &x25519_dalek::PublicKey,
+    recipient: &x25519_dalek::PublicKey,
+) -> eyre::Result<Cipher> {
+    ensure!(
+        shared.was_contributory(),
+        "X25519 shared secret is all zeros"
+    );
+    let mut salt = [0_u8; 64];
+    salt[..32].copy_from_slice(ephemeral.as_bytes());
+    salt[32..].copy_from_slice(recipient.as_bytes());
+    let mut wrapping_key = Key::<Cipher>::default();
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(b"classified x25519 wrap", &mut wrapping_key)
+        .expect("32 bytes is a valid HKDF output length");
+    let cipher = Cipher::new(&wrapping_key);
+    wrapping_key.zeroize();
+    Ok(cipher)
+}
+
+impl PublicKey {
+    pub fn to_age(self) -> String {
+        age::format_recipient(&self.0)
+    }
+
+    pub fn wrap(
+        &self,
+        data_key: &Key<Cipher>,
+        rng: impl RngCore + CryptoRng,
+    ) -> eyre::Result<Recipient> {
+        let ephemeral = EphemeralSecret::random_from_rng(rng);
+        let ephemeral_public = x25519_dalek::PublicKey::from(&ephemeral);
+        let shared = ephemeral.diffie_hellman(&self.0);
+        // every wrapping key is used exactly once, so the nonce can be fixed
+        let wrapped = wrapping_cipher(&shared, &ephemeral_public, &self.0)?
+            .encrypt(&Nonce::<Cipher>::default(), data_key.as_slice())
+            .map_err(|_| eyre!("failed to wrap data key"))?;
+        Ok(Recipient::X25519 {
+            ephemeral: ephemeral_public.as_bytes().to_vec(),
+            wrapped,
+        })
+    }
+}
+
+// Returns `None` if the data key is not wrapped for this key
+pub fn unwrap(
+    key: &ArmoredKey,
+    ephemeral: &[u8],
+    wrapped: &[u8],
+) -> eyre::Result<Option<ArmoredKey>> {
+    let ephemeral = <[u8; 32]>::try_from(ephemeral)
+        .map(x25519_dalek::PublicKey::from)
+        .map_err(|_| eyre!("wrong ephemeral key length"))?;
+    let secret = secret(key);
+    let shared = secret.diffie_hellman(&ephemeral);
+    let cipher = wrapping_cipher(&shared, &ephemeral, &(&secret).into())?;
+    let Ok(mut data_key) = cipher.decrypt(&Nonce::<Cipher>::default(), wrapped) else {
+        return Ok(None);
+    };
+    let res = Key::<Cipher>::from_exact_iter(data_key.iter().copied())
+        .ok_or_else(|| eyre!("wrong data key length"));
+    data_key.zeroize();
+    res.map(|key| Some(ArmoredKey::new(key)))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use chacha20poly1305::{KeyInit as _, XChaCha20Poly1305 as Cipher};
+
+    use super::{public, unwrap, PublicKey};
+    use crate::{envelope::Recipient, key::ArmoredKey};
+
+    #[test]
+    fn wrap() {
+        let mut rng = rand::thread_rng();
+        let key = ArmoredKey::new(Cipher::generate_key(&mut rng));
+        let other = ArmoredKey::new(Cipher::generate_key(&mut rng));
+        let data_key = Cipher::generate_key(&mut rng);
+
+        let public_key: PublicKey = public(&key).to_string().parse().unwrap();
+        let Recipient::X25519 { ephemeral, wrapped } =
+            public_key.wrap(&data_key, &mut rng).unwrap()
+        else {
+            unreachable!()
+        };
+        assert_eq!(
+            *unwrap(&key, &ephemeral, &wrapped).unwrap().unwrap(),
+            data_key
+        );
+        assert!(unwrap(&other, &ephemeral, &wrapped).unwrap().is_none());
+    }
+}